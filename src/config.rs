@@ -1,7 +1,16 @@
-use std::time::Duration;
+use std::{
+    ffi::OsString,
+    num::{NonZeroU32, NonZeroUsize},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use anyhow::anyhow;
+use anyhow::Context;
+use clap::parser::ValueSource;
 use clap::ArgAction;
+use clap::ArgMatches;
+use clap::CommandFactory;
 use clap::Parser;
 use clap::ValueEnum;
 use serde::Serialize;
@@ -9,30 +18,221 @@ use serde::Serializer;
 use serde_json::to_value;
 use tracing::level_filters::LevelFilter;
 
+use crate::zvuk::parse_header;
+use crate::zvuk::HttpVersion;
+use crate::zvuk::LyricsFormat;
 use crate::zvuk::Quality;
+use crate::zvuk::QualityFallback;
+use crate::zvuk::ReleaseType;
+use crate::zvuk::SkipExisting;
+use crate::zvuk::StripFeat;
+use crate::zvuk::TotalTracks;
+use crate::zvuk::ZVUK_BUILTIN_RESIZE_COMMAND;
 use crate::zvuk::ZVUK_DEFAULT_COVER_RESIZE_COMMAND;
+use crate::zvuk::ZVUK_LYRICS_PLACEHOLDERS;
 use crate::zvuk::ZVUK_USER_AGENT;
 
 /// Download albums and tracks in high quality (FLAC) from Zvuk.com
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, Parser, Serialize)]
 #[command(author, version, about, long_about = None)]
 pub struct Config {
     #[allow(clippy::doc_markdown)]
-    /// URLs of releases or tracks
+    /// URLs of releases, tracks, playlists or artists
     ///
-    /// URLs must look like https://zvuk.com/track/128672726 or https://zvuk.com/release/29970563
-    #[arg(required = true, num_args = 1..)]
+    /// URLs must look like https://zvuk.com/track/128672726, https://zvuk.com/release/29970563, https://zvuk.com/playlist/8473651 or https://zvuk.com/artist/123
+    #[arg(
+        required_unless_present_any = ["print_config_and_exit", "urls_file"],
+        num_args = 1..,
+    )]
     pub urls: Vec<String>,
 
+    /// File with URLs, one per line, `-` reads URLs from stdin.
+    /// Blank lines and lines starting with `#` are ignored
+    #[arg(long, env)]
+    pub urls_file: Option<PathBuf>,
+
+    /// TOML or JSON file with options.
+    /// Keys are option names like `embed_cover`, command line arguments
+    /// and environment variables take precedence
+    #[serde(skip)]
+    #[arg(long = "config", env = "CONFIG")]
+    pub settings_file: Option<PathBuf>,
+
     /// Zvuk Token
     #[serde(serialize_with = "mask")]
-    #[arg(long, env, hide_env_values = true)]
-    pub token: String,
+    #[arg(
+        long,
+        env,
+        hide_env_values = true,
+        required_unless_present_any = ["token_from_keyring", "print_config_and_exit"]
+    )]
+    pub token: Option<String>,
+
+    /// Read token from system keyring.
+    /// Falls back to --token if keyring has no token
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub token_from_keyring: bool,
+
+    /// Save token provided with --token to system keyring
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+        requires = "token",
+    )]
+    pub save_token_to_keyring: bool,
+
+    /// Write audio of a single track to stdout instead of saving it.
+    /// Tags and cover are not written
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub stdout: bool,
+
+    /// Write download progress to stdout as newline-delimited JSON events:
+    /// `started` (with track quality), `progress`, `done`, `failed` and
+    /// `skipped`
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+        conflicts_with_all = ["stdout", "list"],
+    )]
+    pub json_lines: bool,
 
     /// Quality of tracks to grab
     #[arg(long, short, env, value_enum, default_value_t = Quality::Flac)]
     pub quality: Quality,
 
+    /// What to do with tracks that have no FLAC when FLAC quality is
+    /// requested
+    #[arg(long, env, value_enum, default_value_t = QualityFallback::Skip)]
+    pub quality_fallback: QualityFallback,
+
+    /// Album folder name.
+    /// Placeholders: {artist}, {album}, {year}, {label}.
+    /// Use / to create nested folders
+    #[arg(long, env, default_value = "{artist} - {album} ({year})")]
+    pub dir_template: String,
+
+    /// Track file name.
+    /// Placeholders: {track}, {title}, {artist}, {album}, {genre}, {ext}.
+    /// Use / to create nested folders
+    #[arg(long, env, default_value = "{track} - {title}.{ext}")]
+    pub file_template: String,
+
+    /// Get metadata of tracks without downloading them
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub dry_run: bool,
+
+    /// Write metadata of requested tracks and their releases to JSON file
+    #[arg(long, env)]
+    pub metadata_json: Option<PathBuf>,
+
+    /// Download and tag tracks again even if they are already downloaded
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub overwrite: bool,
+
+    /// Skip tracks with the same track id tag in current folder and its
+    /// subfolders regardless of file names. Only FLAC files are checked
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub skip_by_id: bool,
+
+    /// Which already downloaded tracks to skip
+    #[arg(long, env, value_enum, default_value_t = SkipExisting::Any)]
+    pub skip_existing: SkipExisting,
+
+    /// Add quality to file names, e.g. `01 - Title [FLAC].flac`
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub quality_in_filename: bool,
+
+    /// Skip tracks shorter than this, e.g. `30s`.
+    /// Zero disables the filter
+    #[arg(
+        long,
+        env,
+        default_value = "0s",
+        value_parser = humantime::parse_duration,
+    )]
+    pub min_duration: Duration,
+
+    /// Reject FLAC tracks with lower bit depth, e.g. 24
+    #[arg(long, env)]
+    pub min_bit_depth: Option<u8>,
+
+    /// Reject FLAC tracks with lower sample rate in Hz, e.g. 48000
+    #[arg(long, env)]
+    pub min_sample_rate: Option<u32>,
+
+    /// Save downloaded files as is without writing tags and lyrics.
+    /// Cover is still saved if requested
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+        conflicts_with = "rewrite_tags",
+    )]
+    pub no_tag: bool,
+
     /// Embed album cover into tracks
     #[arg(
         long,
@@ -45,6 +245,23 @@ pub struct Config {
     )]
     pub embed_cover: bool,
 
+    /// Save album cover as cover.jpg or cover.png in album folder
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = true,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub save_cover: bool,
+
+    /// Size of downloaded cover, e.g. `original` or `1000x1000`.
+    /// `default` lets server choose the size
+    #[arg(long, env, default_value = "original")]
+    pub cover_size: String,
+
     /// Resize album cover
     #[arg(
         long,
@@ -61,6 +278,40 @@ pub struct Config {
     #[arg(long, env, default_value_t = 2 * 1000 * 1000)]
     pub resize_cover_limit: u64,
 
+    /// Don't embed covers smaller than this value in bytes.
+    /// Helps to avoid embedding placeholder images
+    #[arg(long, env, default_value_t = 0)]
+    pub min_cover_size: u64,
+
+    /// Use this image as album cover instead of downloading it
+    #[arg(long, env)]
+    pub cover_file: Option<PathBuf>,
+
+    /// Don't download or resize cover if album folder already has one
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub no_touch_existing_cover: bool,
+
+    /// Hardlink covers identical to ones already downloaded in this run
+    /// instead of writing another copy
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub cover_dedupe: bool,
+
     /// Download and embed lyrics
     #[arg(
         long,
@@ -73,8 +324,22 @@ pub struct Config {
     )]
     pub download_lyrics: bool,
 
+    /// How to save synchronized lyrics
+    #[arg(long, env, value_enum, default_value_t = LyricsFormat::Embed)]
+    pub lyrics_format: LyricsFormat,
+
+    /// Lyrics equal to one of these texts are not embedded
+    #[arg(
+        long,
+        env,
+        value_delimiter = ',',
+        default_values_t = ZVUK_LYRICS_PLACEHOLDERS.map(String::from),
+    )]
+    pub lyrics_placeholder: Vec<String>,
+
     /// Resize cover command.
-    /// By default uses imagemagick
+    /// By default uses imagemagick, "builtin" resizes cover without
+    /// external programs
     #[arg(
         long,
         env,
@@ -91,6 +356,26 @@ pub struct Config {
     )]
     pub user_agent: String,
 
+    /// Extra header for API requests in `Name: Value` form.
+    /// Can be used multiple times
+    #[serde(serialize_with = "mask")]
+    #[arg(long, env, value_parser = header_validator)]
+    pub header: Vec<String>,
+
+    /// Extra cookie for zvuk.com in `name=value` form.
+    /// Can be used multiple times
+    #[serde(serialize_with = "mask")]
+    #[arg(long, env, value_parser = cookie_validator)]
+    pub cookie: Vec<String>,
+
+    /// Proxy URL for all requests (http://, https:// or socks5://)
+    #[arg(long, env = "HTTPS_PROXY")]
+    pub proxy: Option<String>,
+
+    /// HTTP protocol version to use
+    #[arg(long, env, value_enum, default_value_t = HttpVersion::Auto)]
+    pub http_version: HttpVersion,
+
     /// How long to wait between getting track links
     #[arg(
         long,
@@ -101,6 +386,221 @@ pub struct Config {
     )]
     pub pause_between_getting_track_links: Duration,
 
+    /// How long to wait between starting track downloads
+    #[arg(
+        long,
+        env,
+        default_value = "0s",
+        value_parser = humantime::parse_duration,
+    )]
+    pub download_delay: Duration,
+
+    /// Maximum number of HTTP requests per second, including covers and
+    /// lyrics. Not limited by default
+    #[arg(long, env)]
+    pub max_requests_per_second: Option<NonZeroU32>,
+
+    /// Timeout for connecting to servers, applies to all requests
+    #[arg(
+        long,
+        env,
+        default_value = "30s",
+        value_parser = humantime::parse_duration,
+    )]
+    pub connect_timeout: Duration,
+
+    /// Timeout for metadata requests
+    #[arg(
+        long,
+        env,
+        default_value = "30s",
+        value_parser = humantime::parse_duration,
+    )]
+    pub metadata_timeout: Duration,
+
+    /// Timeout for downloading tracks and covers.
+    /// No timeout by default
+    #[arg(
+        long,
+        env,
+        value_parser = humantime::parse_duration,
+    )]
+    pub download_timeout: Option<Duration>,
+
+    /// Get this many track links in parallel.
+    /// By default links are fetched one by one
+    #[arg(long, env)]
+    pub parallel_links: Option<NonZeroUsize>,
+
+    /// Download this many tracks in parallel
+    #[arg(long, env, default_value = "1")]
+    pub concurrency: NonZeroUsize,
+
+    /// Maximum number of ids in one metadata request
+    #[arg(long, env, default_value = "50")]
+    pub batch_size: NonZeroUsize,
+
+    /// How many times to retry requests failed with network or server errors
+    #[arg(long, env, default_value_t = 3)]
+    pub max_retries: u32,
+
+    /// Delay before the first retry of a failed request.
+    /// Doubled on every next retry
+    #[arg(
+        long,
+        env,
+        default_value = "1s",
+        value_parser = humantime::parse_duration,
+    )]
+    pub retry_base_delay: Duration,
+
+    /// Retry failed tracks after all other tracks are processed
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = true,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub retry_failed_at_end: bool,
+
+    /// How many times to retry failed tracks at the end
+    #[arg(long, env, default_value_t = 1)]
+    pub retry_rounds: u32,
+
+    /// Write .m3u8 playlist with downloaded tracks into every album folder
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub write_playlist: bool,
+
+    /// Write release.json with release and tracks metadata into every
+    /// album folder
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub per_release_json: bool,
+
+    /// Download tracks to this folder first and move them to album folder
+    /// when download is complete
+    #[arg(long, env)]
+    pub temp_dir: Option<PathBuf>,
+
+    /// Directory to store releases snapshots in.
+    /// When set, only tracks added or changed since the last run are
+    /// downloaded
+    #[arg(long, env)]
+    pub snapshot_dir: Option<PathBuf>,
+
+    /// File with genre replacements, one `from=to` per line
+    #[arg(long, env)]
+    pub genre_map_file: Option<PathBuf>,
+
+    /// Skip tracks with the same ISRC as another track downloaded in this run
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub dedupe_by_isrc: bool,
+
+    /// What to do with featured artists in track titles
+    #[arg(long, env, value_enum, default_value_t = StripFeat::None)]
+    pub strip_feat: StripFeat,
+
+    /// Release types to download from artist URLs, all types by default
+    #[arg(long, env, value_enum, value_delimiter = ',')]
+    pub release_types: Vec<ReleaseType>,
+
+    /// Skip releases released before this year
+    #[arg(long, env)]
+    pub min_year: Option<i32>,
+
+    /// Skip releases released after this year
+    #[arg(long, env)]
+    pub max_year: Option<i32>,
+
+    /// What to write as total tracks tag
+    #[arg(long, env, value_enum, default_value_t = TotalTracks::Release)]
+    pub total_tracks: TotalTracks,
+
+    /// Stop on the first track that failed to download
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub fail_fast: bool,
+
+    /// Continue downloading other tracks when authorization fails.
+    /// By default the whole run is aborted
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub continue_on_auth_failure: bool,
+
+    /// Log HTTP requests and responses headers with debug level
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+    )]
+    pub verbose_http: bool,
+
+    /// Print track listing of releases and exit without downloading
+    #[arg(long, conflicts_with_all = ["stdout", "rewrite_tags"])]
+    pub list: bool,
+
+    /// Rewrite tags of already downloaded releases without downloading
+    /// tracks. Files are matched by name or by track id in tags
+    #[arg(
+        long,
+        env,
+        action = ArgAction::Set,
+        default_value_t = false,
+        default_missing_value = "true",
+        require_equals = true,
+        num_args=0..=1,
+        conflicts_with = "stdout",
+    )]
+    pub rewrite_tags: bool,
+
+    /// Print effective configuration as JSON and exit
+    #[arg(long = "config-dump", alias = "show-config")]
+    pub print_config_and_exit: bool,
+
     /// Verbosity of logging
     #[arg(long, value_enum, env, default_value_t = LogLevel::Debug)]
     pub log_level: LogLevel,
@@ -108,6 +608,10 @@ pub struct Config {
     /// Format of logs
     #[arg(long, value_enum, env, default_value_t = LogFormat::Console)]
     pub log_format: LogFormat,
+
+    /// Also write logs to this file
+    #[arg(long, env)]
+    pub log_file: Option<PathBuf>,
 }
 
 #[derive(ValueEnum, Debug, Clone, Copy, Serialize)]
@@ -141,6 +645,167 @@ impl From<LogLevel> for LevelFilter {
     }
 }
 
+const KEYRING_SERVICE: &str = env!("CARGO_PKG_NAME");
+const KEYRING_USER: &str = "token";
+
+impl Config {
+    /// Parses command line arguments and environment variables and fills
+    /// options that are not set by them from `--config` file
+    pub fn load() -> anyhow::Result<Self> {
+        let args = std::env::args_os().collect::<Vec<_>>();
+        // required options can be in config file, so don't validate yet
+        let matches =
+            Self::command().ignore_errors(true).get_matches_from(&args);
+        let Some(path) = matches.get_one::<PathBuf>("settings_file") else {
+            return Ok(Self::parse_from(args));
+        };
+
+        let file_args = settings_file_args(&matches, &read_settings(path)?)
+            .with_context(|| {
+                format!("Invalid config file {}", path.display())
+            })?;
+        Ok(Self::parse_from(args.into_iter().chain(file_args)))
+    }
+
+    /// Adds URLs from `--urls-file` to URLs from command line
+    pub fn resolve_urls(&mut self) -> anyhow::Result<()> {
+        let Some(path) = &self.urls_file else {
+            return Ok(());
+        };
+        let text = if path.as_os_str() == "-" {
+            std::io::read_to_string(std::io::stdin())
+                .context("Failed to read URLs from stdin")?
+        } else {
+            std::fs::read_to_string(path).with_context(|| {
+                format!("Failed to read URLs from {}", path.display())
+            })?
+        };
+        self.urls.extend(parse_urls(&text));
+        if self.urls.is_empty() {
+            return Err(anyhow!("No URLs to download"));
+        }
+        Ok(())
+    }
+
+    /// Reads token from system keyring if requested and saves provided
+    /// token to it
+    pub fn resolve_token(&mut self) -> anyhow::Result<()> {
+        let entry = || {
+            keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)
+                .context("Failed to open system keyring")
+        };
+
+        if self.save_token_to_keyring {
+            if let Some(token) = &self.token {
+                entry()?
+                    .set_password(token)
+                    .context("Failed to save token to system keyring")?;
+                tracing::info!("Token is saved to system keyring");
+            }
+        }
+
+        if self.token_from_keyring {
+            match entry()?.get_password() {
+                Ok(token) => self.token = Some(token),
+                Err(e) if self.token.is_some() => {
+                    tracing::warn!(
+                        "Failed to read token from system keyring, using --token: {e}"
+                    );
+                },
+                Err(e) => {
+                    return Err(e)
+                        .context("Failed to read token from system keyring");
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_settings(path: &Path) -> anyhow::Result<serde_json::Value> {
+    let text = std::fs::read_to_string(path).with_context(|| {
+        format!("Failed to read config file {}", path.display())
+    })?;
+    let settings = if path.extension().is_some_and(|x| x == "json") {
+        serde_json::from_str(&text)?
+    } else {
+        toml::from_str(&text)?
+    };
+    Ok(settings)
+}
+
+/// Converts options from config file to command line arguments, skipping
+/// options set with command line arguments or environment variables
+fn settings_file_args(
+    matches: &ArgMatches,
+    settings: &serde_json::Value,
+) -> anyhow::Result<Vec<OsString>> {
+    let command = Config::command();
+    let mut args = Vec::new();
+    for (key, value) in settings
+        .as_object()
+        .context("config file must contain a table of options")?
+    {
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == key.as_str())
+            .with_context(|| format!("Unknown option {key}"))?;
+        if matches!(
+            matches.value_source(key),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        ) {
+            continue;
+        }
+
+        let values = match value {
+            serde_json::Value::Array(values) => values.clone(),
+            value => vec![value.clone()],
+        };
+        for value in values {
+            // flags like `--list` take no value, they are repeated as many
+            // times as they are set
+            let times = match (arg.get_action(), &value) {
+                (ArgAction::SetTrue, serde_json::Value::Bool(value)) => {
+                    Some(u64::from(*value))
+                },
+                (ArgAction::Count, serde_json::Value::Number(value)) => {
+                    Some(value.as_u64().with_context(|| {
+                        format!("Option {key} must be a positive number")
+                    })?)
+                },
+                (ArgAction::SetTrue | ArgAction::Count, _) => {
+                    return Err(anyhow!("Option {key} has unsupported value"))
+                },
+                _ => None,
+            };
+            if let Some(times) = times {
+                let long = arg
+                    .get_long()
+                    .with_context(|| format!("Option {key} has no name"))?;
+                for _ in 0..times {
+                    args.push(format!("--{long}").into());
+                }
+                continue;
+            }
+
+            let value = match value {
+                serde_json::Value::String(value) => value,
+                serde_json::Value::Number(_) | serde_json::Value::Bool(_) => {
+                    value.to_string()
+                },
+                _ => {
+                    return Err(anyhow!("Option {key} has unsupported value"))
+                },
+            };
+            args.push(match arg.get_long() {
+                Some(long) => format!("--{long}={value}").into(),
+                None => value.into(),
+            });
+        }
+    }
+    Ok(args)
+}
+
 pub fn mask<S, T>(_: &T, s: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -170,8 +835,19 @@ where
     }
 }
 
+/// Gets URLs from text with URL per line skipping blank lines and comments
+fn parse_urls(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect()
+}
+
 fn resize_command_validator(value: &str) -> anyhow::Result<String> {
-    if value.contains("{source}") && value.contains("{target}") {
+    if value == ZVUK_BUILTIN_RESIZE_COMMAND
+        || value.contains("{source}") && value.contains("{target}")
+    {
         return Ok(String::from(value));
     }
     Err(anyhow!(
@@ -179,14 +855,42 @@ fn resize_command_validator(value: &str) -> anyhow::Result<String> {
     ))
 }
 
+fn header_validator(value: &str) -> anyhow::Result<String> {
+    parse_header(value)?;
+    Ok(String::from(value))
+}
+
+fn cookie_validator(value: &str) -> anyhow::Result<String> {
+    match value.split_once('=') {
+        Some((name, _)) if !name.trim().is_empty() => Ok(String::from(value)),
+        _ => Err(anyhow!("cookie must look like `name=value`")),
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use clap::CommandFactory;
+
+    use super::cookie_validator;
+    use super::header_validator;
+    use super::parse_urls;
     use super::resize_command_validator;
+    use super::settings_file_args;
     use super::Config;
 
+    #[test]
+    fn parse_urls_file() {
+        let text = "# wishlist\nhttps://zvuk.com/release/1\n\n  https://zvuk.com/track/2  \n";
+
+        assert_eq!(
+            parse_urls(text),
+            vec!["https://zvuk.com/release/1", "https://zvuk.com/track/2"]
+        );
+    }
+
     #[test]
     fn validate_resize_command() {
-        let successes = &["cmd {source} {target}"];
+        let successes = &["cmd {source} {target}", "builtin"];
         let fails = &["cmd {target}", "cmd {source}", "cmd", ""];
 
         for case in successes {
@@ -198,9 +902,60 @@ mod tests {
         }
     }
 
+    #[test]
+    fn validate_headers_and_cookies() {
+        assert!(header_validator("X-Csrf-Token: abc").is_ok());
+        assert!(header_validator("X-Empty:").is_ok());
+        assert!(header_validator("X-Csrf-Token abc").is_err());
+        assert!(header_validator("Bad Name: abc").is_err());
+        assert!(header_validator(": abc").is_err());
+
+        assert!(cookie_validator("session=abc").is_ok());
+        assert!(cookie_validator("session").is_err());
+        assert!(cookie_validator("=abc").is_err());
+    }
+
+    #[test]
+    fn config_file_args() {
+        let matches = Config::command().get_matches_from([
+            "zvuk-dl",
+            "--token=cli",
+            "--quality=mp3-high",
+            "https://zvuk.com/track/1",
+        ]);
+        let settings = serde_json::json!({
+            "quality": "flac",
+            "embed_cover": true,
+            "retry_rounds": 2,
+            "lyrics_placeholder": ["one", "two"],
+            "list": true,
+            "print_config_and_exit": false,
+        });
+
+        assert_eq!(
+            settings_file_args(&matches, &settings).unwrap(),
+            [
+                "--embed-cover=true",
+                "--list",
+                "--lyrics-placeholder=one",
+                "--lyrics-placeholder=two",
+                "--retry-rounds=2",
+            ]
+        );
+        assert!(settings_file_args(
+            &matches,
+            &serde_json::json!({"list": "yes"})
+        )
+        .is_err());
+        assert!(settings_file_args(
+            &matches,
+            &serde_json::json!({"no_such_option": 1})
+        )
+        .is_err());
+    }
+
     #[test]
     fn verify_cli() {
-        use clap::CommandFactory;
         Config::command().debug_assert();
     }
 }
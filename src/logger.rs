@@ -1,13 +1,20 @@
-use tracing::level_filters::LevelFilter;
-use tracing_subscriber::EnvFilter;
+use std::{fs::File, path::Path, sync::Mutex};
+
+use anyhow::Context;
+use tracing::{level_filters::LevelFilter, Subscriber};
+use tracing_subscriber::{
+    fmt::MakeWriter, layer::SubscriberExt, registry::LookupSpan,
+    util::SubscriberInitExt, EnvFilter, Layer,
+};
 
 use crate::config::{LogFormat, LogLevel};
 
 pub fn setup(
     log_level: LogLevel,
     log_format: LogFormat,
+    log_file: Option<&Path>,
     bin_name: Option<&str>,
-) {
+) -> anyhow::Result<()> {
     let log_level: LevelFilter = log_level.into();
 
     let with_color = supports_color::on(supports_color::Stream::Stderr)
@@ -27,13 +34,35 @@ pub fn setup(
             .expect("hardcoded filter should be correct")
     });
 
-    let builder = tracing_subscriber::fmt()
-        .with_env_filter(filter)
-        .with_writer(std::io::stderr)
-        .with_ansi(with_color);
+    let mut layers = vec![layer(log_format, std::io::stderr, with_color)];
+    if let Some(path) = log_file {
+        let file = File::create(path).with_context(|| {
+            format!("Failed to create log file {}", path.display())
+        })?;
+        layers.push(layer(log_format, Mutex::new(file), false));
+    }
 
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layers)
+        .init();
+    Ok(())
+}
+
+fn layer<S, W>(
+    log_format: LogFormat,
+    writer: W,
+    with_ansi: bool,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(with_ansi);
     match log_format {
-        LogFormat::Console => builder.init(),
-        LogFormat::Json => builder.json().flatten_event(true).init(),
+        LogFormat::Console => layer.boxed(),
+        LogFormat::Json => layer.json().flatten_event(true).boxed(),
     }
 }
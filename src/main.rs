@@ -1,8 +1,10 @@
 mod config;
 mod logger;
+mod progress;
+mod ratelimit;
+mod snapshot;
 mod zvuk;
 
-use clap::Parser;
 use dotenvy::dotenv;
 
 use config::Config;
@@ -10,14 +12,22 @@ use config::LogStruct;
 
 fn main() -> anyhow::Result<()> {
     dotenv().ok();
-    let config = Config::parse();
+    let mut config = Config::load()?;
+
+    if config.print_config_and_exit {
+        println!("{}", serde_json::to_string_pretty(&config)?);
+        return Ok(());
+    }
 
     logger::setup(
         config.log_level,
         config.log_format,
+        config.log_file.as_deref(),
         option_env!("CARGO_BIN_NAME"),
-    );
+    )?;
 
+    config.resolve_token()?;
+    config.resolve_urls()?;
     config.log();
 
     zvuk::download(&config)?;
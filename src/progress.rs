@@ -0,0 +1,282 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde::Serialize;
+
+/// Receives download progress events.
+///
+/// All methods do nothing by default
+pub trait Progress: Send + Sync {
+    /// Track download is about to start
+    fn on_track_started(&self, _track_id: &str, _path: &Path, _quality: &str) {
+    }
+
+    /// Part of track data is downloaded
+    fn on_bytes(
+        &self,
+        _track_id: &str,
+        _downloaded: u64,
+        _total: Option<u64>,
+    ) {
+    }
+
+    /// Track is downloaded and tagged
+    fn on_track_done(&self, _track_id: &str, _path: &Path) {}
+
+    /// Track download or processing failed
+    fn on_track_failed(&self, _track_id: &str, _error: &anyhow::Error) {}
+
+    /// Track is not going to be downloaded. `path` is set for tracks that
+    /// are already downloaded
+    fn on_track_skipped(
+        &self,
+        _track_id: &str,
+        _reason: &str,
+        _path: Option<&Path>,
+    ) {
+    }
+}
+
+/// Reports progress to logs
+pub struct LogProgress;
+
+impl Progress for LogProgress {
+    fn on_track_done(&self, _track_id: &str, path: &Path) {
+        tracing::debug!("Downloaded {}", path.display());
+    }
+}
+
+/// Progress event written by [`JsonLinesProgress`]
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    Started {
+        track_id: &'a str,
+        path: &'a Path,
+        quality: &'a str,
+    },
+    Progress {
+        track_id: &'a str,
+        downloaded: u64,
+        total: Option<u64>,
+    },
+    Done {
+        track_id: &'a str,
+        path: &'a Path,
+    },
+    Failed {
+        track_id: &'a str,
+        error: String,
+    },
+    Skipped {
+        track_id: &'a str,
+        reason: &'a str,
+        path: Option<&'a Path>,
+    },
+}
+
+/// Writes progress events as newline-delimited JSON
+pub struct JsonLinesProgress<W> {
+    out: Mutex<W>,
+    /// Bytes downloaded when the last progress event was written by track id
+    reported: Mutex<HashMap<String, u64>>,
+}
+
+/// Minimum number of bytes between progress events of a track
+const JSON_LINES_PROGRESS_STEP: u64 = 256 * 1024;
+
+impl<W: Write> JsonLinesProgress<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out: Mutex::new(out),
+            reported: Mutex::default(),
+        }
+    }
+
+    fn write(&self, event: &Event) {
+        let mut out = self.out.lock().expect("output lock is poisoned");
+        let result = serde_json::to_writer(&mut *out, event)
+            .map_err(std::io::Error::from)
+            .and_then(|()| writeln!(out))
+            .and_then(|()| out.flush());
+        drop(out);
+        if let Err(e) = result {
+            tracing::warn!("Failed to write progress event: {e}");
+        }
+    }
+}
+
+impl<W: Write + Send> Progress for JsonLinesProgress<W> {
+    fn on_track_started(&self, track_id: &str, path: &Path, quality: &str) {
+        self.write(&Event::Started {
+            track_id,
+            path,
+            quality,
+        });
+    }
+
+    fn on_bytes(&self, track_id: &str, downloaded: u64, total: Option<u64>) {
+        let mut reported =
+            self.reported.lock().expect("reported lock is poisoned");
+        let last = reported.get(track_id).copied().unwrap_or_default();
+        if downloaded.saturating_sub(last) < JSON_LINES_PROGRESS_STEP
+            && Some(downloaded) != total
+        {
+            return;
+        }
+        reported.insert(track_id.to_owned(), downloaded);
+        drop(reported);
+        self.write(&Event::Progress {
+            track_id,
+            downloaded,
+            total,
+        });
+    }
+
+    fn on_track_done(&self, track_id: &str, path: &Path) {
+        self.reported
+            .lock()
+            .expect("reported lock is poisoned")
+            .remove(track_id);
+        self.write(&Event::Done { track_id, path });
+    }
+
+    fn on_track_failed(&self, track_id: &str, error: &anyhow::Error) {
+        self.reported
+            .lock()
+            .expect("reported lock is poisoned")
+            .remove(track_id);
+        self.write(&Event::Failed {
+            track_id,
+            error: format!("{error:#}"),
+        });
+    }
+
+    fn on_track_skipped(
+        &self,
+        track_id: &str,
+        reason: &str,
+        path: Option<&Path>,
+    ) {
+        self.write(&Event::Skipped {
+            track_id,
+            reason,
+            path,
+        });
+    }
+}
+
+/// Reader that reports how many bytes were read through it
+pub struct ProgressReader<'a, R> {
+    inner: R,
+    progress: &'a dyn Progress,
+    track_id: &'a str,
+    downloaded: u64,
+    total: Option<u64>,
+}
+
+impl<'a, R> ProgressReader<'a, R> {
+    pub fn new(
+        inner: R,
+        progress: &'a dyn Progress,
+        track_id: &'a str,
+        total: Option<u64>,
+    ) -> Self {
+        Self {
+            inner,
+            progress,
+            track_id,
+            downloaded: 0,
+            total,
+        }
+    }
+
+    /// Counts `downloaded` bytes as already read, e.g. for resumed downloads
+    pub const fn with_offset(mut self, downloaded: u64) -> Self {
+        self.downloaded = downloaded;
+        self
+    }
+}
+
+impl<R: Read> Read for ProgressReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if read > 0 {
+            self.downloaded += read as u64;
+            self.progress
+                .on_bytes(self.track_id, self.downloaded, self.total);
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, sync::Mutex};
+
+    use super::{JsonLinesProgress, Progress, ProgressReader};
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(u64, Option<u64>)>>);
+
+    impl Progress for Recorder {
+        fn on_bytes(
+            &self,
+            _track_id: &str,
+            downloaded: u64,
+            total: Option<u64>,
+        ) {
+            self.0.lock().unwrap().push((downloaded, total));
+        }
+    }
+
+    #[test]
+    fn reader_reports_bytes() {
+        let recorder = Recorder::default();
+        let data = [0_u8; 10];
+        let mut reader =
+            ProgressReader::new(data.as_slice(), &recorder, "1", Some(10));
+
+        let mut buf = [0_u8; 4];
+        while reader.read(&mut buf).unwrap() > 0 {}
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![(4, Some(10)), (8, Some(10)), (10, Some(10))]
+        );
+    }
+
+    #[test]
+    fn json_lines_events() {
+        let progress = JsonLinesProgress::new(Vec::new());
+        let path = std::path::Path::new("01 - Title.flac");
+
+        progress.on_track_started("1", path, "flac");
+        progress.on_bytes("1", 1, Some(300 * 1024));
+        progress.on_bytes("1", 300 * 1024, Some(300 * 1024));
+        progress.on_track_failed("2", &anyhow::anyhow!("HTTP 403"));
+        progress.on_track_done("1", path);
+        progress.on_track_skipped("3", "already downloaded", Some(path));
+
+        let out = progress.out.into_inner().unwrap();
+        let events = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect::<Vec<serde_json::Value>>();
+        assert_eq!(
+            events,
+            vec![
+                serde_json::json!({"event": "started", "track_id": "1", "path": "01 - Title.flac", "quality": "flac"}),
+                serde_json::json!({"event": "progress", "track_id": "1", "downloaded": 307_200, "total": 307_200}),
+                serde_json::json!({"event": "failed", "track_id": "2", "error": "HTTP 403"}),
+                serde_json::json!({"event": "done", "track_id": "1", "path": "01 - Title.flac"}),
+                serde_json::json!({"event": "skipped", "track_id": "3", "reason": "already downloaded", "path": "01 - Title.flac"}),
+            ]
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::{
+    num::NonZeroU32,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Token bucket that can be shared between threads.
+///
+/// Allows `burst` calls at once, then one call per `interval`
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    burst: u32,
+    /// When the next call is allowed if the bucket is empty
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// Allows `rate` calls per second with bursts of up to `rate` calls
+    pub fn per_second(rate: NonZeroU32) -> Self {
+        Self::new(Duration::from_secs(1) / rate.get(), rate.get())
+    }
+
+    /// Allows one call per `interval` without bursts
+    pub fn with_interval(interval: Duration) -> Self {
+        Self::new(interval, 1)
+    }
+
+    fn new(interval: Duration, burst: u32) -> Self {
+        Self {
+            interval,
+            burst,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Takes a token and returns how long the caller has to wait before
+    /// using it
+    fn reserve(&self) -> Duration {
+        let now = Instant::now();
+        let mut next =
+            self.next.lock().expect("rate limiter lock is poisoned");
+        let slot = (*next).max(now);
+        *next = slot + self.interval;
+        drop(next);
+        let burst = self.interval * (self.burst - 1);
+        slot.saturating_duration_since(now + burst)
+    }
+
+    /// Blocks until a token is available
+    pub fn acquire(&self) {
+        let wait = self.reserve();
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{num::NonZeroU32, time::Duration};
+
+    use super::RateLimiter;
+
+    #[test]
+    fn rate_limiter_bursts() {
+        let limiter = RateLimiter::per_second(NonZeroU32::new(4).unwrap());
+        for _ in 0..4 {
+            assert!(limiter.reserve().is_zero());
+        }
+        assert!(limiter.reserve() > Duration::from_millis(200));
+
+        let limiter = RateLimiter::with_interval(Duration::from_secs(10));
+        assert!(limiter.reserve().is_zero());
+        assert!(limiter.reserve() > Duration::from_secs(9));
+    }
+}
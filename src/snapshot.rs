@@ -0,0 +1,118 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+/// Release metadata stored between runs to detect changed tracks
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ReleaseSnapshot {
+    pub album: String,
+    /// Track titles by track id
+    pub tracks: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl ReleaseSnapshot {
+    fn path(dir: &Path, release_id: &str) -> PathBuf {
+        dir.join(format!("{release_id}.json"))
+    }
+
+    pub fn load(dir: &Path, release_id: &str) -> anyhow::Result<Option<Self>> {
+        let path = Self::path(dir, release_id);
+        if !path.try_exists()? {
+            return Ok(None);
+        }
+        let data = std::fs::read(&path).with_context(|| {
+            format!("Failed to read snapshot {}", path.display())
+        })?;
+        let snapshot = serde_json::from_slice(&data).with_context(|| {
+            format!("Failed to parse snapshot {}", path.display())
+        })?;
+        Ok(Some(snapshot))
+    }
+
+    pub fn save(&self, dir: &Path, release_id: &str) -> anyhow::Result<()> {
+        std::fs::create_dir_all(dir).with_context(|| {
+            format!("Failed to create folder {}", dir.display())
+        })?;
+        let path = Self::path(dir, release_id);
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?).with_context(
+            || format!("Failed to write snapshot {}", path.display()),
+        )
+    }
+
+    /// Compares this snapshot with the previous one
+    pub fn diff(&self, previous: &Self) -> SnapshotDiff {
+        let mut diff = SnapshotDiff::default();
+        for (track_id, title) in &self.tracks {
+            match previous.tracks.get(track_id) {
+                None => diff.added.push(track_id.clone()),
+                Some(old_title) if old_title != title => {
+                    diff.changed.push(track_id.clone());
+                },
+                Some(_) => {},
+            }
+        }
+        for track_id in previous.tracks.keys() {
+            if !self.tracks.contains_key(track_id) {
+                diff.removed.push(track_id.clone());
+            }
+        }
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReleaseSnapshot, SnapshotDiff};
+
+    fn snapshot(tracks: &[(&str, &str)]) -> ReleaseSnapshot {
+        ReleaseSnapshot {
+            album: String::from("Album"),
+            tracks: tracks
+                .iter()
+                .map(|(id, title)| ((*id).to_owned(), (*title).to_owned()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn diff_snapshots() {
+        let previous = snapshot(&[("1", "One"), ("2", "Two"), ("3", "Three")]);
+        let current =
+            snapshot(&[("1", "One"), ("2", "Two (Remix)"), ("4", "Four")]);
+
+        assert_eq!(
+            current.diff(&previous),
+            SnapshotDiff {
+                added: vec![String::from("4")],
+                changed: vec![String::from("2")],
+                removed: vec![String::from("3")],
+            }
+        );
+        assert_eq!(current.diff(&current), SnapshotDiff::default());
+    }
+
+    #[test]
+    fn save_and_load_snapshot() {
+        let dir = std::env::temp_dir()
+            .join(format!("zvuk-dl-snapshot-{}", std::process::id()));
+        let current = snapshot(&[("1", "One")]);
+
+        assert!(ReleaseSnapshot::load(&dir, "42").unwrap().is_none());
+        current.save(&dir, "42").unwrap();
+        let loaded = ReleaseSnapshot::load(&dir, "42").unwrap().unwrap();
+        assert_eq!(loaded.tracks, current.tracks);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +1,13 @@
 use std::{
-    collections::{HashMap, HashSet},
-    fmt::Display,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::{Display, Write as _},
+    num::NonZeroUsize,
     path::{Path, PathBuf},
-    time::Duration,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, SystemTime},
 };
 
 use anyhow::Context;
@@ -13,29 +18,71 @@ use chrono::{Datelike, NaiveDate};
 use clap::ValueEnum;
 use id3::{frame, TagLike};
 use reqwest::{
+    blocking::{RequestBuilder, Response},
     cookie::Jar,
-    header::{HeaderMap, USER_AGENT},
-    Url,
+    header::{
+        HeaderMap, HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE,
+        COOKIE, RANGE, RETRY_AFTER, USER_AGENT,
+    },
+    StatusCode, Url,
 };
 use serde::Serialize;
 
-use crate::config::Config;
+use crate::{
+    config::Config,
+    progress::{JsonLinesProgress, LogProgress, Progress, ProgressReader},
+    ratelimit::RateLimiter,
+    snapshot::ReleaseSnapshot,
+};
 
 const ZVUK_HOST: &str = "https://zvuk.com";
 const ZVUK_RELEASE_PREFIX: &str = "https://zvuk.com/release/";
 const ZVUK_TRACKS_PREFIX: &str = "https://zvuk.com/track/";
+const ZVUK_PLAYLIST_PREFIX: &str = "https://zvuk.com/playlist/";
+const ZVUK_ARTIST_PREFIX: &str = "https://zvuk.com/artist/";
 const ZVUK_RELEASES_URL: &str = "https://zvuk.com/api/tiny/releases";
 const ZVUK_LABELS_URL: &str = "https://zvuk.com/api/tiny/labels";
+const ZVUK_PLAYLISTS_URL: &str = "https://zvuk.com/api/tiny/playlists";
 const ZVUK_TRACKS_URL: &str = "https://zvuk.com/api/tiny/tracks";
 const ZVUK_DOWNLOAD_URL: &str = "https://zvuk.com/api/tiny/track/stream";
 const ZVUK_LYRICS_URL: &str = "https://zvuk.com/api/tiny/lyrics";
+const ZVUK_GRAPHQL_URL: &str = "https://zvuk.com/api/v1/graphql";
+
+const ZVUK_ARTIST_RELEASES_QUERY: &str = "query getArtistReleases($id: ID!, $limit: Int!, $offset: Int!) { getArtists(ids: [$id]) { releases(limit: $limit, offset: $offset) { id type } } }";
+/// Number of artist releases requested at once
+const ZVUK_ARTIST_RELEASES_PAGE: usize = 100;
+
+/// Longest `Retry-After` to wait for, requests asking to wait longer fail
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Words in API errors about invalid or expired token
+const AUTH_ERROR_WORDS: [&str; 7] = [
+    "auth",
+    "unauthorized",
+    "unauthenticated",
+    "token",
+    "invalid_token",
+    "token_expired",
+    "login",
+];
+
+/// Extensions of album cover files, the first one is the default
+const COVER_EXTENSIONS: [&str; 2] = ["jpg", "png"];
 
 pub const ZVUK_DEFAULT_COVER_RESIZE_COMMAND: &str =
     "magick {source} -define jpeg:extent=1MB {target}";
+/// Cover size that lets server choose the size
+pub const ZVUK_DEFAULT_COVER_SIZE: &str = "default";
+
+/// Resize command that resizes cover without external programs
+pub const ZVUK_BUILTIN_RESIZE_COMMAND: &str = "builtin";
+
+/// Texts zvuk.com returns instead of lyrics
+pub const ZVUK_LYRICS_PLACEHOLDERS: [&str; 1] = ["Текст песни отсутствует"];
 
 pub const ZVUK_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36";
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 struct ReleaseInfo {
     track_ids: Vec<String>,
     track_count: u32,
@@ -45,22 +92,164 @@ struct ReleaseInfo {
     author: String,
 }
 
-#[expect(unused)]
 #[derive(Debug)]
+struct PlaylistInfo {
+    title: String,
+    /// Track ids in playlist order
+    track_ids: Vec<String>,
+}
+
+impl TryFrom<&serde_json::Value> for PlaylistInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &serde_json::Value) -> Result<Self, Self::Error> {
+        Ok(Self {
+            title: value
+                .get("title")
+                .and_then(|x| x.as_str())
+                .context("title is not a string")?
+                .to_string(),
+            track_ids: value
+                .get("track_ids")
+                .and_then(|x| x.as_array())
+                .context("track_ids is not an array")?
+                .iter()
+                .filter_map(|x| Some(x.as_number()?.to_string()))
+                .collect(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
 struct TrackInfo {
     author: String,
+    /// Separate artist names, empty if zvuk doesn't provide them
+    artists: Vec<String>,
     name: String,
     album: String,
     release_id: String,
     track_id: String,
     genre: String,
     number: u32,
-    image: String,
+    /// Cover URL with `{size}` placeholder
+    image: Option<String>,
     lyrics: bool,
+    isrc: Option<String>,
+    explicit: bool,
+    has_flac: bool,
+    /// Duration in seconds
+    duration: Option<u64>,
+    /// Composers, empty if zvuk doesn't provide them
+    composers: Vec<String>,
+    bpm: Option<u32>,
+    /// 1 if track can be downloaded
+    availability: Option<i64>,
+}
+
+impl TryFrom<&serde_json::Value> for TrackInfo {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &serde_json::Value) -> Result<Self, Self::Error> {
+        Ok(Self {
+            author: value
+                .get("credits")
+                .and_then(|x| x.as_str())
+                .context("credits is not a string")?
+                .to_string(),
+            artists: value
+                .get("artist_names")
+                .and_then(|x| x.as_array())
+                .map(|x| {
+                    x.iter()
+                        .filter_map(|x| x.as_str())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            name: value
+                .get("title")
+                .and_then(|x| x.as_str())
+                .context("title is not a string")?
+                .to_string(),
+            album: value
+                .get("release_title")
+                .and_then(|x| x.as_str())
+                .context("release_title is not a string")?
+                .to_string(),
+            release_id: value
+                .get("release_id")
+                .and_then(|x| x.as_number())
+                .context("release_id is not a number")?
+                .to_string(),
+            track_id: value.get("id").context("no id")?.to_string(),
+            genre: value
+                .get("genres")
+                .and_then(|x| x.as_array())
+                .context("genre is not an array")?
+                .iter()
+                .filter_map(|x| x.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            number: value
+                .get("position")
+                .and_then(serde_json::Value::as_u64)
+                .context("position is not a number")?
+                .try_into()?,
+            image: value
+                .get("image")
+                .and_then(|x| x.get("src"))
+                .and_then(|x| x.as_str())
+                .map(str::to_owned),
+            lyrics: value
+                .get("lyrics")
+                .and_then(serde_json::Value::as_bool)
+                .unwrap_or(false),
+            isrc: value
+                .get("isrc")
+                .and_then(|x| x.as_str())
+                .filter(|x| !x.is_empty())
+                .map(str::to_uppercase),
+            explicit: value
+                .get("explicit")
+                .and_then(serde_json::Value::as_bool)
+                .unwrap_or(false),
+            has_flac: value
+                .get("has_flac")
+                .and_then(serde_json::Value::as_bool)
+                .context("has_flac is not bool")?,
+            duration: value
+                .get("duration")
+                .and_then(serde_json::Value::as_u64),
+            composers: match value.get("composer") {
+                Some(serde_json::Value::String(composer)) => composer
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|x| !x.is_empty())
+                    .map(str::to_owned)
+                    .collect(),
+                Some(serde_json::Value::Array(composers)) => composers
+                    .iter()
+                    .filter_map(|x| x.as_str())
+                    .map(str::to_owned)
+                    .collect(),
+                _ => Vec::new(),
+            },
+            bpm: value
+                .get("bpm")
+                .and_then(serde_json::Value::as_u64)
+                .and_then(|x| u32::try_from(x).ok())
+                .filter(|x| *x > 0),
+            availability: value
+                .get("availability")
+                .and_then(serde_json::Value::as_i64),
+        })
+    }
 }
 
 #[derive(ValueEnum, Debug, Clone, Serialize)]
 pub enum Quality {
+    /// FLAC if track has it, otherwise MP3 320 kbps
+    Auto,
     Flac,
     // 320 kbps
     MP3High,
@@ -71,16 +260,27 @@ pub enum Quality {
 impl Quality {
     fn extension(&self) -> String {
         let string = match self {
-            Self::Flac => "flac",
+            // auto is resolved to track quality before downloading
+            Self::Auto | Self::Flac => "flac",
             Self::MP3High | Self::MP3Mid => "mp3",
         };
         String::from(string)
     }
+
+    /// Human readable quality name
+    const fn label(&self) -> &'static str {
+        match self {
+            Self::Auto | Self::Flac => "FLAC",
+            Self::MP3High => "MP3 320",
+            Self::MP3Mid => "MP3 128",
+        }
+    }
 }
 
 impl Display for Quality {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::Auto => write!(f, "auto"),
             Self::Flac => write!(f, "flac"),
             Self::MP3High => write!(f, "high"),
             Self::MP3Mid => write!(f, "mid"),
@@ -88,73 +288,592 @@ impl Display for Quality {
     }
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, Serialize)]
+pub enum StripFeat {
+    /// Keep titles as is
+    None,
+    /// Remove featured artists from titles
+    Strip,
+    /// Remove featured artists from titles and add them to artists
+    MoveToArtist,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Serialize)]
+pub enum HttpVersion {
+    /// Negotiate protocol version with server
+    Auto,
+    /// Use only HTTP/1.1
+    Http1,
+    /// Use only HTTP/2
+    Http2,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum LyricsFormat {
+    /// Embed lyrics into tracks
+    Embed,
+    /// Write synchronized lyrics to .lrc files next to tracks,
+    /// other lyrics are embedded
+    Lrc,
+    /// Embed lyrics and write synchronized lyrics to .lrc files
+    Both,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Serialize)]
+pub enum TotalTracks {
+    /// Number of tracks in the release
+    Release,
+    /// Number of downloaded tracks from the release
+    Downloaded,
+    /// Don't write total tracks
+    None,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ReleaseType {
+    Album,
+    Single,
+    Compilation,
+}
+
+impl ReleaseType {
+    fn parse(type_: &str) -> Option<Self> {
+        match type_.to_lowercase().as_str() {
+            "album" => Some(Self::Album),
+            "single" => Some(Self::Single),
+            "compilation" => Some(Self::Compilation),
+            _ => None,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum QualityFallback {
+    /// Download track in MP3 320 kbps
+    Allow,
+    /// Skip track
+    Skip,
+    /// Stop with error
+    Fail,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Serialize)]
+pub enum SkipExisting {
+    /// Skip track downloaded in any quality
+    Any,
+    /// Skip track only if it is downloaded in requested quality
+    SameQuality,
+}
+
+/// Zvuk responded with 401 Unauthorized
+#[derive(Debug)]
+struct AuthFailed;
+
+impl Display for AuthFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Authorization failed, token is invalid or expired")
+    }
+}
+
+impl std::error::Error for AuthFailed {}
+
+/// Zvuk responded with unsuccessful HTTP status
+#[derive(Debug)]
+struct HttpStatus {
+    status: StatusCode,
+    host: String,
+    /// How long server asked to wait before retrying
+    retry_after: Option<Duration>,
+}
+
+impl From<&Response> for HttpStatus {
+    fn from(response: &Response) -> Self {
+        Self {
+            status: response.status(),
+            host: response.url().host_str().unwrap_or_default().to_owned(),
+            retry_after: response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|x| x.to_str().ok())
+                .and_then(|x| parse_retry_after(x, SystemTime::now())),
+        }
+    }
+}
+
+impl Display for HttpStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HTTP {} from {}", self.status, self.host)
+    }
+}
+
+impl std::error::Error for HttpStatus {}
+
+/// Downloaded track to list in album playlist
+struct SavedTrack {
+    folder: PathBuf,
+    path: PathBuf,
+    number: u32,
+    duration: Option<u64>,
+    artist: String,
+    title: String,
+}
+
+/// Why a requested track was not downloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum SkipReason {
+    NoFlac,
+    DuplicateIsrc,
+    Unavailable,
+    Exists,
+    RegionLocked,
+    TooShort,
+    BelowMinFormat,
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoFlac => write!(f, "no FLAC quality"),
+            Self::DuplicateIsrc => write!(f, "duplicate ISRC"),
+            Self::Unavailable => write!(f, "unavailable"),
+            Self::Exists => write!(f, "already downloaded"),
+            Self::RegionLocked => {
+                write!(f, "track unavailable in your region")
+            },
+            Self::TooShort => write!(f, "shorter than --min-duration"),
+            Self::BelowMinFormat => {
+                write!(f, "FLAC format lower than requested")
+            },
+        }
+    }
+}
+
 enum LyricsKind {
     Subtitle,
     Lyrics,
 }
 
-#[expect(unused)]
 struct Lyrics {
     kind: LyricsKind,
     text: String,
 }
 
+#[allow(clippy::struct_excessive_bools)]
 struct Client {
     embed_cover: bool,
+    no_tag: bool,
     resize_cover: bool,
     resize_cover_limit: u64,
+    min_cover_size: u64,
     download_lyrics: bool,
+    lyrics_placeholders: Vec<String>,
+    lyrics_format: LyricsFormat,
     resize_command: String,
     quality: Quality,
+    retry_failed_at_end: bool,
+    retry_rounds: u32,
+    snapshot_dir: Option<PathBuf>,
+    strip_feat: StripFeat,
+    total_tracks: TotalTracks,
+    cover_file: Option<PathBuf>,
+    /// Covers copied from `--cover-file` in this run by cover path
+    copied_covers: Mutex<HashMap<PathBuf, PathBuf>>,
+    quality_in_filename: bool,
+    dir_template: String,
+    file_template: String,
+    min_duration: Duration,
+    min_bit_depth: Option<u8>,
+    min_sample_rate: Option<u32>,
+    per_release_json: bool,
+    verbose_http: bool,
+    continue_on_auth_failure: bool,
+    parallel_links: Option<NonZeroUsize>,
+    concurrency: NonZeroUsize,
+    max_retries: u32,
+    write_playlist: bool,
+    skip_by_id: bool,
+    /// Downloaded tracks found by `--skip-by-id` by track id
+    track_files: OnceLock<HashMap<String, PathBuf>>,
+    quality_fallback: QualityFallback,
+    fail_fast: bool,
+    downloaded: AtomicUsize,
+    /// Reasons of failed downloads by track id
+    failures: Mutex<BTreeMap<String, String>>,
+    save_cover: bool,
+    /// Covers saved to temporary folder for embedding
+    temp_covers: Mutex<HashSet<PathBuf>>,
+    cover_size: String,
+    /// Limits all HTTP requests
+    request_limiter: Option<RateLimiter>,
+    /// Limits how often track downloads start
+    download_limiter: Option<RateLimiter>,
+    batch_size: NonZeroUsize,
+    dry_run: bool,
+    metadata_json: Option<PathBuf>,
+    /// Metadata of requested tracks and their releases by track id
+    resolved_metadata: Mutex<BTreeMap<String, serde_json::Value>>,
+    overwrite: bool,
+    skip_existing: SkipExisting,
+    release_types: Vec<ReleaseType>,
+    min_year: Option<i32>,
+    max_year: Option<i32>,
+    saved_tracks: Mutex<Vec<SavedTrack>>,
+    retry_base_delay: Duration,
+    /// Locks held while album cover is downloaded and processed by cover
+    /// path, so only tracks of the same album wait for each other
+    cover_locks: Mutex<HashMap<PathBuf, Arc<Mutex<()>>>>,
 
     pause_between_getting_track_links: Duration,
+    metadata_timeout: Duration,
+    download_timeout: Option<Duration>,
+    genre_map: HashMap<String, String>,
+    progress: Box<dyn Progress>,
+    covers: Mutex<HashMap<PathBuf, Arc<[u8]>>>,
+    cover_dedupe: bool,
+    no_touch_existing_cover: bool,
+    /// Processed covers by hash of downloaded data
+    cover_hashes: Mutex<HashMap<u64, PathBuf>>,
+    temp_dir: Option<PathBuf>,
+    dedupe_by_isrc: bool,
+    isrcs: Mutex<HashMap<String, String>>,
+    /// Tracks with duplicate ISRC by track id of the first copy. They are
+    /// skipped only when the first copy doesn't fail
+    duplicates: Mutex<HashMap<String, Vec<String>>>,
+    /// Skipped tracks by track id
+    skipped: Mutex<HashMap<String, SkipReason>>,
     default_headers: HeaderMap,
     http: reqwest::blocking::Client,
 }
 
 impl Client {
-    fn new(config: &Config) -> Self {
-        let jar = Jar::default();
-        jar.add_cookie_str(
-            format!("auth={}", config.token).as_str(),
-            &ZVUK_HOST.parse::<Url>().unwrap(),
-        );
+    fn new(
+        config: &Config,
+        progress: Box<dyn Progress>,
+    ) -> anyhow::Result<Self> {
         let mut default_headers = HeaderMap::new();
         default_headers.append(USER_AGENT, config.user_agent.parse().unwrap());
+        for header in &config.header {
+            let (name, value) = parse_header(header)
+                .with_context(|| format!("Invalid header {header:?}"))?;
+            default_headers.insert(name, value);
+        }
 
-        Self {
+        let genre_map = match &config.genre_map_file {
+            Some(path) => {
+                parse_genre_map(&std::fs::read_to_string(path).with_context(
+                    || format!("Failed to read genre map {}", path.display()),
+                )?)
+                .with_context(|| {
+                    format!("Failed to parse genre map {}", path.display())
+                })?
+            },
+            None => HashMap::new(),
+        };
+
+        Ok(Self {
             embed_cover: config.embed_cover,
+            no_tag: config.no_tag,
             resize_cover: config.resize_cover,
             resize_cover_limit: config.resize_cover_limit,
+            min_cover_size: config.min_cover_size,
             download_lyrics: config.download_lyrics,
+            lyrics_placeholders: config.lyrics_placeholder.clone(),
+            lyrics_format: config.lyrics_format,
             resize_command: config.resize_command.clone(),
             pause_between_getting_track_links: config
                 .pause_between_getting_track_links,
             quality: config.quality.clone(),
+            retry_failed_at_end: config.retry_failed_at_end,
+            retry_rounds: config.retry_rounds,
+            snapshot_dir: config.snapshot_dir.clone(),
+            strip_feat: config.strip_feat,
+            total_tracks: config.total_tracks,
+            cover_file: config.cover_file.clone(),
+            copied_covers: Mutex::default(),
+            quality_in_filename: config.quality_in_filename,
+            dir_template: config.dir_template.clone(),
+            file_template: config.file_template.clone(),
+            min_duration: config.min_duration,
+            min_bit_depth: config.min_bit_depth,
+            min_sample_rate: config.min_sample_rate,
+            per_release_json: config.per_release_json,
+            verbose_http: config.verbose_http,
+            continue_on_auth_failure: config.continue_on_auth_failure,
+            parallel_links: config.parallel_links,
+            concurrency: config.concurrency,
+            max_retries: config.max_retries,
+            write_playlist: config.write_playlist,
+            skip_by_id: config.skip_by_id,
+            track_files: OnceLock::new(),
+            quality_fallback: config.quality_fallback,
+            fail_fast: config.fail_fast,
+            downloaded: AtomicUsize::new(0),
+            failures: Mutex::default(),
+            save_cover: config.save_cover,
+            temp_covers: Mutex::default(),
+            cover_size: config.cover_size.clone(),
+            request_limiter: config
+                .max_requests_per_second
+                .map(RateLimiter::per_second),
+            download_limiter: (!config.download_delay.is_zero())
+                .then(|| RateLimiter::with_interval(config.download_delay)),
+            batch_size: config.batch_size,
+            dry_run: config.dry_run,
+            metadata_json: config.metadata_json.clone(),
+            resolved_metadata: Mutex::default(),
+            overwrite: config.overwrite,
+            skip_existing: config.skip_existing,
+            release_types: config.release_types.clone(),
+            min_year: config.min_year,
+            max_year: config.max_year,
+            saved_tracks: Mutex::default(),
+            retry_base_delay: config.retry_base_delay,
+            cover_locks: Mutex::default(),
+            metadata_timeout: config.metadata_timeout,
+            download_timeout: config.download_timeout,
+            genre_map,
+            progress,
+            covers: Mutex::default(),
+            cover_dedupe: config.cover_dedupe,
+            no_touch_existing_cover: config.no_touch_existing_cover,
+            cover_hashes: Mutex::default(),
+            temp_dir: config.temp_dir.clone(),
+            dedupe_by_isrc: config.dedupe_by_isrc,
+            isrcs: Mutex::default(),
+            duplicates: Mutex::default(),
+            skipped: Mutex::default(),
 
             default_headers,
-            http: reqwest::blocking::Client::builder()
-                .cookie_provider(jar.into())
-                .build()
-                .unwrap(),
+            http: Self::http_client(config)?,
+        })
+    }
+
+    fn http_client(
+        config: &Config,
+    ) -> anyhow::Result<reqwest::blocking::Client> {
+        let jar = Jar::default();
+        let host = ZVUK_HOST.parse::<Url>().unwrap();
+        jar.add_cookie_str(
+            format!("auth={}", config.token.as_deref().unwrap_or_default())
+                .as_str(),
+            &host,
+        );
+        for cookie in &config.cookie {
+            jar.add_cookie_str(cookie, &host);
+        }
+
+        let mut http = match config.http_version {
+            HttpVersion::Auto => reqwest::blocking::Client::builder(),
+            HttpVersion::Http1 => {
+                reqwest::blocking::Client::builder().http1_only()
+            },
+            HttpVersion::Http2 => {
+                reqwest::blocking::Client::builder().http2_prior_knowledge()
+            },
+        };
+        if let Some(proxy) = &config.proxy {
+            http = http.proxy(
+                reqwest::Proxy::all(proxy)
+                    .with_context(|| format!("Invalid proxy URL {proxy}"))?,
+            );
+        }
+        Ok(http
+            .cookie_provider(jar.into())
+            .connect_timeout(config.connect_timeout)
+            .timeout(None)
+            .build()?)
+    }
+
+    fn metadata_request(&self, url: &str) -> RequestBuilder {
+        self.http
+            .get(url)
+            .headers(self.default_headers.clone())
+            .timeout(self.metadata_timeout)
+    }
+
+    fn download_request(&self, url: &str) -> RequestBuilder {
+        let request = self.http.get(url);
+        match self.download_timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
+        }
+    }
+
+    /// Starts download of a track or a cover without retries.
+    /// Download is resumed from `offset` if server supports it.
+    ///
+    /// Errors include HTTP status and host of the URL, but not the URL
+    /// itself because stream URLs are signed
+    fn send_download(
+        &self,
+        url: &str,
+        offset: u64,
+    ) -> anyhow::Result<Response> {
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|x| x.host_str().map(str::to_owned))
+            .unwrap_or_default();
+        let response = self
+            .send_once(if offset > 0 {
+                self.download_request(url)
+                    .header(RANGE, format!("bytes={offset}-"))
+            } else {
+                self.download_request(url)
+            })
+            .with_context(|| format!("Request to {host} failed"))?;
+        if !response.status().is_success() {
+            return Err(HttpStatus::from(&response).into());
+        }
+        Ok(response)
+    }
+
+    /// Sends request and retries it on network and server errors
+    fn send(&self, request: &RequestBuilder) -> anyhow::Result<Response> {
+        self.with_retries(|| {
+            self.send_once(
+                request.try_clone().context("Request can't be retried")?,
+            )
+        })
+    }
+
+    fn send_once(&self, request: RequestBuilder) -> anyhow::Result<Response> {
+        let response = self.execute(request)?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            return Err(AuthFailed.into());
+        }
+        if response.status().is_server_error()
+            || response.status() == StatusCode::TOO_MANY_REQUESTS
+        {
+            return Err(HttpStatus::from(&response).into());
+        }
+        Ok(response)
+    }
+
+    /// Calls `f` until it succeeds, fails with an error that is not worth
+    /// retrying or `--max-retries` is reached. Delay between attempts is
+    /// doubled every time unless server asks to wait with `Retry-After`
+    fn with_retries<T>(
+        &self,
+        mut f: impl FnMut() -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let mut delay = self.retry_base_delay;
+        let mut attempt = 1;
+        loop {
+            match f() {
+                Err(e) if attempt <= self.max_retries && is_retryable(&e) => {
+                    let Some(wait) = retry_wait(&e, delay) else {
+                        tracing::warn!(
+                            "Server asked to retry later than in {}, giving up",
+                            humantime::format_duration(MAX_RETRY_AFTER)
+                        );
+                        return Err(e);
+                    };
+                    tracing::warn!(
+                        "Attempt {attempt} of {} failed, retrying in {}: {e:#}",
+                        self.max_retries + 1,
+                        humantime::format_duration(wait)
+                    );
+                    std::thread::sleep(wait);
+                    delay = delay.saturating_mul(2);
+                    attempt += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+
+    fn execute(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        if let Some(limiter) = &self.request_limiter {
+            limiter.acquire();
+        }
+        if !self.verbose_http {
+            return request.send();
+        }
+
+        let request = request.build()?;
+        tracing::debug!("HTTP request {} {}", request.method(), request.url());
+        for (name, value) in request.headers() {
+            let value = if name == COOKIE {
+                "******"
+            } else {
+                value.to_str().unwrap_or("<binary>")
+            };
+            tracing::debug!("HTTP request header {name}: {value}");
+        }
+
+        let response = self.http.execute(request)?;
+        tracing::debug!(
+            "HTTP response {} {}",
+            response.status(),
+            response.url()
+        );
+        for (name, value) in response.headers() {
+            let name_str = name.as_str();
+            if name == CONTENT_TYPE
+                || name == CONTENT_LENGTH
+                || name == RETRY_AFTER
+                || name_str.starts_with("x-ratelimit")
+                || name_str.starts_with("ratelimit")
+            {
+                tracing::debug!(
+                    "HTTP response header {name}: {}",
+                    value.to_str().unwrap_or("<binary>")
+                );
+            }
+        }
+        Ok(response)
+    }
+
+    /// Requests metadata by `batch_size` ids at a time and merges results.
+    /// Ids missing from the results are logged
+    fn in_batches<T>(
+        &self,
+        ids: &[String],
+        what: &str,
+        mut fetch: impl FnMut(&[String]) -> anyhow::Result<HashMap<String, T>>,
+    ) -> anyhow::Result<HashMap<String, T>> {
+        let mut results = HashMap::new();
+        for batch in ids.chunks(self.batch_size.get()) {
+            results.extend(fetch(batch)?);
         }
+        let missing = ids
+            .iter()
+            .filter(|id| !results.contains_key(*id))
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            tracing::warn!(
+                "No metadata for {} {what}: {}",
+                missing.len(),
+                missing.join(", ")
+            );
+        }
+        Ok(results)
     }
 
     fn get_labels_info(
         &self,
         label_ids: &[String],
+    ) -> anyhow::Result<HashMap<String, String>> {
+        self.in_batches(label_ids, "labels", |ids| {
+            self.get_labels_info_batch(ids)
+        })
+    }
+
+    fn get_labels_info_batch(
+        &self,
+        label_ids: &[String],
     ) -> anyhow::Result<HashMap<String, String>> {
         tracing::info!("Getting labels metadata");
         let response = self
-            .http
-            .get(ZVUK_LABELS_URL)
-            .query(&[("ids", label_ids.join(","))])
-            .headers(self.default_headers.clone())
-            .send()
+            .send(
+                &self
+                    .metadata_request(ZVUK_LABELS_URL)
+                    .query(&[("ids", label_ids.join(","))]),
+            )
             .context("Failed to download labels metadata")?;
-        let body = response
-            .json::<serde_json::Value>()
-            .context("Failed to parse labels metadata")?;
+        let body =
+            parse_json(response).context("Failed to parse labels metadata")?;
 
         tracing::trace!("{ZVUK_LABELS_URL} response: {body:#?}");
 
@@ -182,18 +901,26 @@ impl Client {
     fn get_releases_info(
         &self,
         release_ids: &[String],
+    ) -> anyhow::Result<HashMap<String, ReleaseInfo>> {
+        self.in_batches(release_ids, "releases", |ids| {
+            self.get_releases_info_batch(ids)
+        })
+    }
+
+    fn get_releases_info_batch(
+        &self,
+        release_ids: &[String],
     ) -> anyhow::Result<HashMap<String, ReleaseInfo>> {
         tracing::info!("Getting releases metadata");
         let response = self
-            .http
-            .get(ZVUK_RELEASES_URL)
-            .query(&[("ids", release_ids.join(","))])
-            .headers(self.default_headers.clone())
-            .send()
+            .send(
+                &self
+                    .metadata_request(ZVUK_RELEASES_URL)
+                    .query(&[("ids", release_ids.join(","))]),
+            )
             .context("Failed to download releases metadata")?;
 
-        let body = response
-            .json::<serde_json::Value>()
+        let body = parse_json(response)
             .context("Failed to parse releses metadata")?;
 
         tracing::trace!("{ZVUK_RELEASES_URL} response: {body:#?}");
@@ -214,6 +941,13 @@ impl Client {
             );
         }
 
+        if label_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        // releases of the same label share the label id
+        label_ids.sort_unstable();
+        label_ids.dedup();
+
         let labels = self.get_labels_info(&label_ids)?;
 
         let mut releases = HashMap::new();
@@ -231,7 +965,27 @@ impl Client {
                 .iter()
                 .filter_map(|x| Some(x.as_number()?.to_string()))
                 .collect();
-            let track_count: u32 = track_ids.len().try_into()?;
+            // track_ids can miss tracks hidden from us, so prefer the
+            // total that zvuk reports for the release
+            let track_count: u32 = match release_info
+                .get("track_count")
+                .and_then(serde_json::Value::as_u64)
+            {
+                Some(count) => count.try_into()?,
+                None => track_ids.len().try_into()?,
+            };
+            let availability = release_info.get("availability");
+            tracing::trace!(
+                "Release id={release_id} availability: {availability:?}"
+            );
+            if availability
+                .and_then(serde_json::Value::as_i64)
+                .is_some_and(|x| x != 1)
+            {
+                tracing::warn!(
+                    "Release id={release_id} is unavailable in your region, some tracks may be skipped"
+                );
+            }
 
             releases.insert(
                 release_id.clone(),
@@ -270,515 +1024,3822 @@ impl Client {
         Ok(releases)
     }
 
-    fn download_albums(&self, release_ids: &[String]) -> anyhow::Result<()> {
-        let mut track_ids = Vec::new();
-        let releases = self
-            .get_releases_info(release_ids)
-            .context("Failed to get releases metadata")?;
+    fn get_playlists_info(
+        &self,
+        playlist_ids: &[String],
+    ) -> anyhow::Result<HashMap<String, PlaylistInfo>> {
+        tracing::info!("Getting playlists metadata");
+        let response = self
+            .send(
+                &self
+                    .metadata_request(ZVUK_PLAYLISTS_URL)
+                    .query(&[("ids", playlist_ids.join(","))]),
+            )
+            .context("Failed to download playlists metadata")?;
 
-        for release_info in releases.values() {
-            track_ids.extend(release_info.track_ids.clone());
-        }
+        let body = parse_json(response)
+            .context("Failed to parse playlists metadata")?;
+        tracing::trace!("{ZVUK_PLAYLISTS_URL} response: {body:#?}");
 
-        self.download_tracks(&track_ids, &releases)
-            .context("Failed to download tracks")?;
-        Ok(())
+        let mut playlists = HashMap::new();
+        for (playlist_id, playlist_info) in body
+            .get("result")
+            .and_then(|x| x.get("playlists"))
+            .and_then(|x| x.as_object())
+            .context("No playlists in playlists metadata")?
+        {
+            playlists.insert(
+                playlist_id.clone(),
+                PlaylistInfo::try_from(playlist_info)?,
+            );
+        }
+        Ok(playlists)
     }
 
-    fn download_tracks(
+    fn download_playlists(
         &self,
-        track_ids: &[String],
-        releases: &HashMap<String, ReleaseInfo>,
+        playlist_ids: &[String],
     ) -> anyhow::Result<()> {
-        let metadata = self
-            .get_tracks_metadata(track_ids)
-            .context("Failed to get tracks metadata")?;
-        let links = self
-            .get_tracks_links(track_ids)
-            .context("Failed to get tracks download links")?;
-
-        if metadata.len() != links.len() {
-            return Err(anyhow::anyhow!(
-                "metadata and links have different length"
-            ));
-        }
-        let releases_ = if releases.is_empty() {
-            let mut release_ids = HashSet::new();
-            for track_info in metadata.values() {
-                release_ids.insert(track_info.release_id.clone());
-            }
-            let release_ids = release_ids.into_iter().collect::<Vec<String>>();
-            &self
-                .get_releases_info(&release_ids)
-                .context("Failed to get releases metadata")?
-        } else {
-            releases
-        };
+        let playlists = self
+            .get_playlists_info(playlist_ids)
+            .context("Failed to get playlists metadata")?;
 
-        for (track_id, track_info) in metadata {
-            let result = self.get_and_save_track(
-                links.get(&track_id).context("no link")?,
-                &track_info,
-                releases_
-                    .get(&track_info.release_id)
-                    .context("no release info")?,
-            );
-            if let Err(e) = result {
+        for playlist_id in playlist_ids {
+            let Some(playlist) = playlists.get(playlist_id) else {
+                tracing::warn!("Playlist {playlist_id} not found");
+                continue;
+            };
+            if playlist.track_ids.is_empty() {
                 tracing::warn!(
-                    "Failed to download and process track id={track_id}: {e:#}"
+                    "Playlist {playlist_id} has no tracks, skipping it"
                 );
+                continue;
             }
+            tracing::info!("Downloading playlist {}", playlist.title);
+            self.download_tracks(
+                &playlist.track_ids,
+                &HashMap::new(),
+                Some(playlist),
+            )
+            .context("Failed to download tracks")?;
         }
         Ok(())
     }
 
-    fn get_tracks_metadata(
+    /// Gets ids of all artist releases of requested types
+    fn get_artist_release_ids(
         &self,
-        track_ids: &[String],
-    ) -> anyhow::Result<HashMap<String, TrackInfo>> {
-        tracing::info!("Getting tracks metadata");
-        let response = self
-            .http
-            .get(ZVUK_TRACKS_URL)
-            .query(&[("ids", track_ids.join(","))])
-            .headers(self.default_headers.clone())
-            .send()
-            .context("Failed to donwload tracks metadata")?;
-
-        let body = response
-            .json::<serde_json::Value>()
-            .context("Failed to parse tracks metadata")?;
-        tracing::trace!("{ZVUK_TRACKS_URL} response: {body:#?}");
+        artist_id: &str,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut release_ids = Vec::new();
+        let mut offset = 0;
+        loop {
+            let response = self
+                .send(
+                    &self
+                        .http
+                        .post(ZVUK_GRAPHQL_URL)
+                        .headers(self.default_headers.clone())
+                        .timeout(self.metadata_timeout)
+                        .json(&serde_json::json!({
+                            "operationName": "getArtistReleases",
+                            "query": ZVUK_ARTIST_RELEASES_QUERY,
+                            "variables": {
+                                "id": artist_id,
+                                "limit": ZVUK_ARTIST_RELEASES_PAGE,
+                                "offset": offset,
+                            },
+                        })),
+                )
+                .context("Failed to download artist releases")?;
+            let body = parse_json(response)
+                .context("Failed to parse artist releases")?;
+            tracing::trace!("{ZVUK_GRAPHQL_URL} response: {body:#?}");
 
-        let mut tracks = HashMap::new();
+            let releases = parse_artist_releases(&body)?;
+            let count = releases.len();
+            release_ids.extend(releases.into_iter().filter_map(
+                |(release_id, type_)| {
+                    (self.release_types.is_empty()
+                        || type_
+                            .is_some_and(|x| self.release_types.contains(&x)))
+                    .then_some(release_id)
+                },
+            ));
+            if count < ZVUK_ARTIST_RELEASES_PAGE {
+                break;
+            }
+            offset += count;
+        }
+        Ok(release_ids)
+    }
 
-        for (track_id, track_info) in body
-            .get("result")
-            .and_then(|x| x.get("tracks"))
-            .and_then(|x| x.as_object())
-            .context("tracks is not an object")?
-        {
-            if matches!(self.quality, Quality::Flac)
-                && !track_info
-                    .get("has_flac")
-                    .and_then(serde_json::Value::as_bool)
-                    .context("has_flac is not bool")?
-            {
+    fn download_artists(&self, artist_ids: &[String]) -> anyhow::Result<()> {
+        for artist_id in artist_ids {
+            let mut release_ids =
+                self.get_artist_release_ids(artist_id).with_context(|| {
+                    format!("Failed to get releases of artist {artist_id}")
+                })?;
+            let mut seen = HashSet::new();
+            release_ids.retain(|x| seen.insert(x.clone()));
+            if release_ids.is_empty() {
                 tracing::warn!(
-                    "track id {track_id} doesn't have FLAC quality available"
+                    "Artist {artist_id} has no releases to download"
                 );
                 continue;
             }
-            tracks.insert(
-                track_id.clone(),
-                TrackInfo {
-                    author: track_info
-                        .get("credits")
-                        .and_then(|x| x.as_str())
-                        .context("credits is not a string")?
-                        .to_string(),
-                    name: track_info
-                        .get("title")
-                        .and_then(|x| x.as_str())
-                        .context("title is not a string")?
-                        .to_string(),
-                    album: track_info
-                        .get("release_title")
-                        .and_then(|x| x.as_str())
-                        .context("release_title is not a string")?
-                        .to_string(),
-                    release_id: track_info
-                        .get("release_id")
-                        .and_then(|x| x.as_number())
-                        .context("release_id is not a number")?
-                        .to_string(),
-                    track_id: track_info
-                        .get("id")
-                        .context("no id")?
-                        .to_string(),
-                    genre: track_info
-                        .get("genres")
-                        .and_then(|x| x.as_array())
-                        .context("genre is not an array")?
+            tracing::info!(
+                "Downloading {} releases of artist {artist_id}",
+                release_ids.len()
+            );
+            self.download_albums(&release_ids)?;
+        }
+        Ok(())
+    }
+
+    fn download_albums(&self, release_ids: &[String]) -> anyhow::Result<()> {
+        let mut track_ids = Vec::new();
+        let mut releases = self
+            .get_releases_info(release_ids)
+            .context("Failed to get releases metadata")?;
+
+        for release_id in release_ids {
+            if !releases.contains_key(release_id) {
+                tracing::warn!(
+                    "Release {release_id} not found, make sure it is a release URL and not a track or an audiobook URL"
+                );
+            }
+        }
+        releases.retain(|release_id, release_info| {
+            if release_info.track_ids.is_empty() {
+                tracing::warn!(
+                    "Release {release_id} has no tracks, skipping it"
+                );
+            }
+            !release_info.track_ids.is_empty()
+        });
+        if self.min_year.is_some() || self.max_year.is_some() {
+            let count = releases.len();
+            releases.retain(|release_id, release_info| {
+                let keep = is_in_years(
+                    &release_info.date,
+                    self.min_year,
+                    self.max_year,
+                );
+                if !keep {
+                    tracing::debug!(
+                        "Release {release_id} from {} is out of years range, skipping it",
+                        release_info.date
+                    );
+                }
+                keep
+            });
+            tracing::info!(
+                "Skipped {} releases out of years range",
+                count - releases.len()
+            );
+        }
+
+        for release_info in releases.values() {
+            track_ids.extend(release_info.track_ids.clone());
+        }
+
+        let metadata = if self.snapshot_dir.is_some() || self.per_release_json
+        {
+            self.get_tracks_metadata(&track_ids)
+                .context("Failed to get tracks metadata")?
+        } else {
+            HashMap::new()
+        };
+
+        if self.per_release_json && !self.dry_run {
+            for (release_id, release_info) in &releases {
+                self.write_release_json(release_id, release_info, &metadata)
+                    .context("Failed to write release metadata")?;
+            }
+        }
+
+        let snapshots = match &self.snapshot_dir {
+            Some(snapshot_dir) => {
+                let snapshots = Self::take_snapshots(&metadata, &releases);
+                track_ids =
+                    Self::changed_since_snapshots(snapshot_dir, &snapshots)?;
+                Some(snapshots)
+            },
+            None => None,
+        };
+
+        let failed = if track_ids.is_empty() {
+            tracing::info!("No tracks to download");
+            Vec::new()
+        } else {
+            self.download_tracks(&track_ids, &releases, None)
+                .context("Failed to download tracks")?
+        };
+
+        if let (Some(snapshot_dir), Some(snapshots), false) =
+            (&self.snapshot_dir, snapshots, self.dry_run)
+        {
+            for (release_id, mut snapshot) in snapshots {
+                // failed tracks must be downloaded on the next run
+                snapshot
+                    .tracks
+                    .retain(|track_id, _| !failed.contains(track_id));
+                snapshot
+                    .save(snapshot_dir, &release_id)
+                    .context("Failed to save release snapshot")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrites tags of already downloaded tracks without downloading audio
+    fn rewrite_tags(&self, release_ids: &[String]) -> anyhow::Result<()> {
+        let releases = self
+            .get_releases_info(release_ids)
+            .context("Failed to get releases metadata")?;
+        let track_ids = releases
+            .values()
+            .flat_map(|release_info| release_info.track_ids.clone())
+            .collect::<Vec<_>>();
+        let metadata = self
+            .get_tracks_metadata(&track_ids)
+            .context("Failed to get tracks metadata")?;
+
+        for (release_id, release_info) in &releases {
+            let path = long_path(&self.release_folder_path(release_info))?;
+            let Some(folder) =
+                find_release_folder(&path, release_id, &self.dir_template)?
+            else {
+                tracing::warn!(
+                    "Release {release_id} is not found in {}, skipping it",
+                    path.display()
+                );
+                continue;
+            };
+            let cover_path = existing_cover(&folder.join("cover.jpg"))?;
+            let cover_path = cover_path.as_deref();
+            let files = match std::fs::read_dir(&folder).and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<Result<Vec<_>, _>>()
+            }) {
+                Ok(files) => files,
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to read folder {}, skipping it: {e}",
+                        folder.display()
+                    );
+                    continue;
+                },
+            };
+
+            let release_track_ids = release_info
+                .track_ids
+                .iter()
+                .map(String::as_str)
+                .collect::<HashSet<_>>();
+
+            for track_id in &release_info.track_ids {
+                let Some(track_info) = metadata.get(track_id) else {
+                    continue;
+                };
+                // track could be downloaded in any quality
+                let mut found = None;
+                for quality in
+                    [Quality::Flac, Quality::MP3High, Quality::MP3Mid]
+                {
+                    let filepath = folder.join(
+                        self.track_filename_with_quality(track_info, &quality),
+                    );
+                    if filepath.try_exists()? {
+                        found = Some((filepath, quality));
+                        break;
+                    }
+                }
+                let found = found.or_else(|| {
+                    files
                         .iter()
-                        .filter_map(|x| x.as_str())
-                        .collect::<Vec<_>>()
-                        .join(", "),
-                    number: track_info
-                        .get("position")
-                        .and_then(serde_json::Value::as_u64)
-                        .context("position is not a number")?
-                        .try_into()?,
-                    image: track_info
-                        .get("image")
-                        .and_then(|x| x.get("src"))
-                        .and_then(|x| x.as_str())
-                        .context("image src is not a string")?
-                        .replace("&size={size}&ext=jpg", ""),
-                    lyrics: track_info
-                        .get("lyrics")
-                        .and_then(serde_json::Value::as_bool)
-                        .unwrap_or(false),
+                        .find(|path| {
+                            file_track_id(path).as_deref()
+                                == Some(track_id.as_str())
+                        })
+                        .map(|path| (path.clone(), file_quality(path)))
+                });
+                let Some((filepath, quality)) = found else {
+                    tracing::warn!(
+                        "Track id={track_id} is not found in {}, skipping it",
+                        folder.display()
+                    );
+                    continue;
+                };
+
+                tracing::info!("Rewriting tags {}", filepath.display());
+                self.write_tags(
+                    &filepath,
+                    &quality,
+                    cover_path,
+                    track_info,
+                    release_info,
+                    self.total_tracks(release_info, &release_track_ids)?,
+                )
+                .with_context(|| {
+                    format!("Failed to rewrite tags {}", filepath.display())
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_release_json(
+        &self,
+        release_id: &str,
+        release_info: &ReleaseInfo,
+        metadata: &HashMap<String, TrackInfo>,
+    ) -> anyhow::Result<()> {
+        #[derive(Serialize)]
+        struct ReleaseJson<'a> {
+            release_id: &'a str,
+            #[serde(flatten)]
+            release: &'a ReleaseInfo,
+            tracks: Vec<&'a TrackInfo>,
+        }
+
+        let mut tracks = release_info
+            .track_ids
+            .iter()
+            .filter_map(|track_id| metadata.get(track_id))
+            .collect::<Vec<_>>();
+        tracks.sort_by_key(|track_info| track_info.number);
+
+        let folder = self.release_folder(release_id, release_info)?;
+        let path = folder.join("release.json");
+        tracing::info!("Writing release metadata {}", path.display());
+        std::fs::write(
+            &path,
+            serde_json::to_vec_pretty(&ReleaseJson {
+                release_id,
+                release: release_info,
+                tracks,
+            })?,
+        )
+        .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    fn take_snapshots(
+        metadata: &HashMap<String, TrackInfo>,
+        releases: &HashMap<String, ReleaseInfo>,
+    ) -> HashMap<String, ReleaseSnapshot> {
+        let mut snapshots = HashMap::new();
+        for (release_id, release_info) in releases {
+            let tracks = release_info
+                .track_ids
+                .iter()
+                .filter_map(|track_id| {
+                    let track_info = metadata.get(track_id)?;
+                    Some((track_id.clone(), track_info.name.clone()))
+                })
+                .collect();
+            snapshots.insert(
+                release_id.clone(),
+                ReleaseSnapshot {
+                    album: release_info.album.clone(),
+                    tracks,
+                },
+            );
+        }
+        snapshots
+    }
+
+    /// Returns ids of tracks that are new or changed since stored snapshots
+    fn changed_since_snapshots(
+        snapshot_dir: &Path,
+        snapshots: &HashMap<String, ReleaseSnapshot>,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut track_ids = Vec::new();
+        for (release_id, snapshot) in snapshots {
+            let Some(previous) =
+                ReleaseSnapshot::load(snapshot_dir, release_id)
+                    .context("Failed to load release snapshot")?
+            else {
+                track_ids.extend(snapshot.tracks.keys().cloned());
+                continue;
+            };
+
+            let diff = snapshot.diff(&previous);
+            tracing::info!(
+                "Release {release_id} ({}) since last snapshot: {} added, {} changed, {} removed",
+                snapshot.album,
+                diff.added.len(),
+                diff.changed.len(),
+                diff.removed.len(),
+            );
+            for track_id in &diff.removed {
+                tracing::warn!(
+                    "Track id={track_id} ({}) was removed from release {release_id}",
+                    previous.tracks.get(track_id).map_or("", String::as_str),
+                );
+            }
+            track_ids.extend(diff.added);
+            track_ids.extend(diff.changed);
+        }
+        Ok(track_ids)
+    }
+
+    /// Downloads tracks with retries and returns ids of tracks that failed.
+    /// Tracks of a playlist are saved into playlist folder
+    fn download_tracks(
+        &self,
+        track_ids: &[String],
+        releases: &HashMap<String, ReleaseInfo>,
+        playlist: Option<&PlaylistInfo>,
+    ) -> anyhow::Result<Vec<String>> {
+        let batch =
+            track_ids.iter().map(String::as_str).collect::<HashSet<_>>();
+        let mut failed =
+            self.download_tracks_round(track_ids, &batch, releases, playlist)?;
+
+        if self.retry_failed_at_end {
+            for round in 1..=self.retry_rounds {
+                if failed.is_empty() {
+                    break;
+                }
+                tracing::info!(
+                    "Retrying {} failed tracks (round {round} of {})",
+                    failed.len(),
+                    self.retry_rounds
+                );
+                failed = match self
+                    .download_tracks_round(&failed, &batch, releases, playlist)
+                {
+                    Ok(failed) => failed,
+                    Err(e) if e.is::<AuthFailed>() => return Err(e),
+                    Err(e) => {
+                        tracing::warn!("Failed to retry tracks: {e:#}");
+                        break;
+                    },
+                };
+            }
+        }
+        if self.dedupe_by_isrc {
+            loop {
+                let restored = self.take_duplicates(&failed);
+                if restored.is_empty() {
+                    break;
+                }
+                tracing::info!(
+                    "Downloading {} tracks with the same ISRC as failed tracks",
+                    restored.len()
+                );
+                failed.extend(self.download_tracks_round(
+                    &restored, &batch, releases, playlist,
+                )?);
+            }
+        }
+
+        if !failed.is_empty() {
+            tracing::warn!(
+                "Failed to download {} tracks: {}",
+                failed.len(),
+                failed.join(", ")
+            );
+        }
+        if self.write_playlist {
+            self.write_playlists()
+                .context("Failed to write album playlists")?;
+        }
+        Ok(failed)
+    }
+
+    /// Downloads tracks once and returns ids of tracks that failed.
+    /// `batch` is all tracks requested to download
+    fn download_tracks_round(
+        &self,
+        track_ids: &[String],
+        batch: &HashSet<&str>,
+        releases: &HashMap<String, ReleaseInfo>,
+        playlist: Option<&PlaylistInfo>,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut metadata = self
+            .get_tracks_metadata(track_ids)
+            .context("Failed to get tracks metadata")?;
+        for track_id in track_ids {
+            if !metadata.contains_key(track_id) {
+                self.skip(track_id, SkipReason::Unavailable);
+            }
+        }
+        if self.dedupe_by_isrc {
+            self.remove_duplicates(track_ids, &mut metadata);
+        }
+        if !self.min_duration.is_zero() {
+            self.remove_short(&mut metadata);
+        }
+
+        let track_ids = track_ids
+            .iter()
+            .filter(|track_id| metadata.contains_key(*track_id))
+            .cloned()
+            .collect::<Vec<_>>();
+        let releases_ = if releases.is_empty() {
+            let mut release_ids = HashSet::new();
+            for track_info in metadata.values() {
+                release_ids.insert(track_info.release_id.clone());
+            }
+            let release_ids = release_ids.into_iter().collect::<Vec<String>>();
+            &self
+                .get_releases_info(&release_ids)
+                .context("Failed to get releases metadata")?
+        } else {
+            releases
+        };
+
+        self.record_metadata(&metadata, releases_);
+        if self.dry_run {
+            for track_info in metadata.values() {
+                tracing::info!(
+                    "Would download track id={} {} - {}",
+                    track_info.track_id,
+                    track_info.author,
+                    track_info.name
+                );
+            }
+            return Ok(Vec::new());
+        }
+
+        let links = self
+            .get_tracks_links(&track_ids, &metadata)
+            .context("Failed to get tracks download links")?;
+
+        if metadata.len() != links.len() {
+            return Err(anyhow::anyhow!(
+                "metadata and links have different length"
+            ));
+        }
+
+        let queue = Mutex::new(metadata.into_iter());
+        let failed = Mutex::new(Vec::new());
+        let worker = || -> anyhow::Result<()> {
+            loop {
+                let next = queue
+                    .lock()
+                    .expect("tracks queue lock is poisoned")
+                    .next();
+                let Some((track_id, mut track_info)) = next else {
+                    return Ok(());
+                };
+                if let Some(limiter) = &self.download_limiter {
+                    limiter.acquire();
+                }
+                let result = self.download_track(
+                    &mut track_info,
+                    links.get(&track_id).context("no link")?,
+                    releases_,
+                    batch,
+                    playlist,
+                );
+                if let Err(e) = result {
+                    if self.fail_fast
+                        || e.is::<AuthFailed>()
+                            && !self.continue_on_auth_failure
+                    {
+                        // stop other workers
+                        queue
+                            .lock()
+                            .expect("tracks queue lock is poisoned")
+                            .by_ref()
+                            .for_each(drop);
+                        return Err(e);
+                    }
+                    self.track_failed(&track_id, &e);
+                    failed
+                        .lock()
+                        .expect("failed tracks lock is poisoned")
+                        .push(track_id);
+                }
+            }
+        };
+
+        self.run_workers(worker)?;
+        Ok(failed.into_inner().expect("failed tracks lock is poisoned"))
+    }
+
+    /// Reports track that failed to download
+    fn track_failed(&self, track_id: &str, e: &anyhow::Error) {
+        tracing::warn!(
+            "Failed to download and process track id={track_id}: {e:#}"
+        );
+        self.progress.on_track_failed(track_id, e);
+        self.failures
+            .lock()
+            .expect("failures lock is poisoned")
+            .insert(track_id.to_owned(), e.root_cause().to_string());
+    }
+
+    /// Runs `worker` in `concurrency` threads and returns the first error
+    fn run_workers(
+        &self,
+        worker: impl Fn() -> anyhow::Result<()> + Sync,
+    ) -> anyhow::Result<()> {
+        if self.concurrency.get() == 1 {
+            return worker();
+        }
+        std::thread::scope(|scope| {
+            let handles = (0..self.concurrency.get())
+                .map(|_| scope.spawn(&worker))
+                .collect::<Vec<_>>();
+            let mut result = Ok(());
+            for handle in handles {
+                let worker_result = handle
+                    .join()
+                    .unwrap_or_else(|e| std::panic::resume_unwind(e));
+                if result.is_ok() {
+                    result = worker_result;
+                }
+            }
+            result
+        })
+    }
+
+    fn download_track(
+        &self,
+        track_info: &mut TrackInfo,
+        url: &str,
+        releases: &HashMap<String, ReleaseInfo>,
+        batch: &HashSet<&str>,
+        playlist: Option<&PlaylistInfo>,
+    ) -> anyhow::Result<()> {
+        let release_info = releases
+            .get(&track_info.release_id)
+            .context("no release info")?;
+        let total_tracks = match playlist {
+            Some(playlist) => {
+                track_info.number = playlist
+                    .track_ids
+                    .iter()
+                    .position(|x| *x == track_info.track_id)
+                    .context("track is not in playlist")?
+                    .saturating_add(1)
+                    .try_into()?;
+                self.playlist_total_tracks(playlist, batch)?
+            },
+            None => self.total_tracks(release_info, batch)?,
+        };
+        self.get_and_save_track(
+            url,
+            track_info,
+            release_info,
+            total_tracks,
+            playlist,
+        )
+    }
+
+    /// Records that track is not going to be downloaded.
+    /// Only the first reason is recorded for every track
+    fn skip(&self, track_id: &str, reason: SkipReason) {
+        self.skip_file(track_id, reason, None);
+    }
+
+    /// Records that track is not going to be downloaded. `path` is the
+    /// already downloaded file of the track, if any
+    fn skip_file(
+        &self,
+        track_id: &str,
+        reason: SkipReason,
+        path: Option<&Path>,
+    ) {
+        let mut skipped =
+            self.skipped.lock().expect("skipped lock is poisoned");
+        if !skipped.contains_key(track_id) {
+            match path {
+                Some(path) => tracing::info!(
+                    "Skipping track id={track_id}: {reason}, {}",
+                    path.display()
+                ),
+                None => {
+                    tracing::warn!("Skipping track id={track_id}: {reason}");
+                },
+            }
+            skipped.insert(track_id.to_owned(), reason);
+            drop(skipped);
+            self.progress.on_track_skipped(
+                track_id,
+                &reason.to_string(),
+                path,
+            );
+        }
+    }
+
+    /// Logs how many tracks were downloaded, skipped and failed.
+    /// Returns error if any track failed
+    fn log_summary(&self) -> anyhow::Result<()> {
+        tracing::info!(
+            "Downloaded {} tracks",
+            self.downloaded.load(Ordering::Relaxed)
+        );
+        self.log_skipped();
+
+        let failures = std::mem::take(
+            &mut *self.failures.lock().expect("failures lock is poisoned"),
+        );
+        if failures.is_empty() {
+            return Ok(());
+        }
+        for (track_id, reason) in &failures {
+            tracing::error!("Failed track id={track_id}: {reason}");
+        }
+        Err(anyhow::anyhow!(
+            "Failed to download {} tracks",
+            failures.len()
+        ))
+    }
+
+    /// Logs how many tracks were skipped and why
+    fn log_skipped(&self) {
+        let mut reasons = BTreeMap::new();
+        for reason in self
+            .skipped
+            .lock()
+            .expect("skipped lock is poisoned")
+            .values()
+        {
+            *reasons.entry(*reason).or_insert(0_usize) += 1;
+        }
+        if reasons.is_empty() {
+            return;
+        }
+        tracing::warn!(
+            "Skipped {} tracks: {}",
+            reasons.values().sum::<usize>(),
+            reasons
+                .iter()
+                .map(|(reason, count)| format!("{reason}: {count}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    /// Removes tracks with ISRC of another track downloaded in this run and
+    /// sets them aside until that track is downloaded
+    fn remove_duplicates(
+        &self,
+        track_ids: &[String],
+        metadata: &mut HashMap<String, TrackInfo>,
+    ) {
+        let mut isrcs = self.isrcs.lock().expect("isrcs lock is poisoned");
+        let mut duplicates =
+            self.duplicates.lock().expect("duplicates lock is poisoned");
+        for track_id in track_ids {
+            let Some(isrc) =
+                metadata.get(track_id).and_then(|x| x.isrc.clone())
+            else {
+                continue;
+            };
+            match isrcs.get(&isrc) {
+                Some(seen_track_id) if seen_track_id != track_id => {
+                    tracing::info!(
+                        "Track id={track_id} with ISRC {isrc} is the same recording as track id={seen_track_id}"
+                    );
+                    duplicates
+                        .entry(seen_track_id.clone())
+                        .or_default()
+                        .push(track_id.clone());
+                    metadata.remove(track_id);
+                },
+                Some(_) => {},
+                None => {
+                    isrcs.insert(isrc, track_id.clone());
                 },
+            }
+        }
+    }
+
+    /// Skips tracks set aside by [`Self::remove_duplicates`] whose first copy
+    /// is downloaded. Returns tracks whose first copy is in `failed`, so
+    /// they can be downloaded instead
+    fn take_duplicates(&self, failed: &[String]) -> Vec<String> {
+        let duplicates = std::mem::take(
+            &mut *self.duplicates.lock().expect("duplicates lock is poisoned"),
+        );
+        let mut restored = Vec::new();
+        for (track_id, duplicate_ids) in duplicates {
+            if failed.contains(&track_id) {
+                self.isrcs
+                    .lock()
+                    .expect("isrcs lock is poisoned")
+                    .retain(|_, x| *x != track_id);
+                restored.extend(duplicate_ids);
+            } else {
+                for duplicate_id in &duplicate_ids {
+                    self.skip(duplicate_id, SkipReason::DuplicateIsrc);
+                }
+            }
+        }
+        restored
+    }
+
+    /// Removes tracks shorter than `--min-duration`.
+    /// Tracks without duration are kept
+    fn remove_short(&self, metadata: &mut HashMap<String, TrackInfo>) {
+        metadata.retain(|track_id, track_info| {
+            let short = track_info
+                .duration
+                .is_some_and(|x| Duration::from_secs(x) < self.min_duration);
+            if short {
+                self.skip(track_id, SkipReason::TooShort);
+            }
+            !short
+        });
+    }
+
+    fn total_tracks(
+        &self,
+        release_info: &ReleaseInfo,
+        batch: &HashSet<&str>,
+    ) -> anyhow::Result<Option<u32>> {
+        Ok(match self.total_tracks {
+            TotalTracks::Release => Some(release_info.track_count),
+            TotalTracks::Downloaded => Some(
+                release_info
+                    .track_ids
+                    .iter()
+                    .filter(|track_id| batch.contains(track_id.as_str()))
+                    .count()
+                    .try_into()?,
+            ),
+            TotalTracks::None => None,
+        })
+    }
+
+    fn playlist_total_tracks(
+        &self,
+        playlist: &PlaylistInfo,
+        batch: &HashSet<&str>,
+    ) -> anyhow::Result<Option<u32>> {
+        Ok(match self.total_tracks {
+            TotalTracks::Release => Some(playlist.track_ids.len().try_into()?),
+            TotalTracks::Downloaded => Some(batch.len().try_into()?),
+            TotalTracks::None => None,
+        })
+    }
+
+    fn get_tracks_metadata(
+        &self,
+        track_ids: &[String],
+    ) -> anyhow::Result<HashMap<String, TrackInfo>> {
+        let mut tracks = self.fetch_tracks_metadata(track_ids)?;
+        tracks.retain(|track_id, track_info| {
+            tracing::trace!(
+                "Track id={track_id} availability: {:?}",
+                track_info.availability
+            );
+            let available = track_info.availability.is_none_or(|x| x == 1);
+            if !available {
+                self.skip(track_id, SkipReason::RegionLocked);
+            }
+            available
+        });
+        if !matches!(self.quality, Quality::Flac) {
+            return Ok(tracks);
+        }
+        match self.quality_fallback {
+            QualityFallback::Allow => {},
+            QualityFallback::Skip => tracks.retain(|track_id, track_info| {
+                if !track_info.has_flac {
+                    self.skip(track_id, SkipReason::NoFlac);
+                }
+                track_info.has_flac
+            }),
+            QualityFallback::Fail => {
+                let mut no_flac = tracks
+                    .values()
+                    .filter(|x| !x.has_flac)
+                    .map(|x| x.track_id.as_str())
+                    .collect::<Vec<_>>();
+                if !no_flac.is_empty() {
+                    no_flac.sort_unstable();
+                    return Err(anyhow::anyhow!(
+                        "Tracks have no FLAC quality: {}",
+                        no_flac.join(", ")
+                    ));
+                }
+            },
+        }
+        Ok(tracks)
+    }
+
+    /// Gets metadata of tracks regardless of available qualities
+    fn fetch_tracks_metadata(
+        &self,
+        track_ids: &[String],
+    ) -> anyhow::Result<HashMap<String, TrackInfo>> {
+        self.in_batches(track_ids, "tracks", |ids| {
+            self.fetch_tracks_metadata_batch(ids)
+        })
+    }
+
+    fn fetch_tracks_metadata_batch(
+        &self,
+        track_ids: &[String],
+    ) -> anyhow::Result<HashMap<String, TrackInfo>> {
+        if track_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        tracing::info!("Getting tracks metadata");
+        let response = self
+            .send(
+                &self
+                    .metadata_request(ZVUK_TRACKS_URL)
+                    .query(&[("ids", track_ids.join(","))]),
+            )
+            .context("Failed to donwload tracks metadata")?;
+
+        let body =
+            parse_json(response).context("Failed to parse tracks metadata")?;
+        tracing::trace!("{ZVUK_TRACKS_URL} response: {body:#?}");
+
+        let mut tracks = HashMap::new();
+
+        for (track_id, track_info) in body
+            .get("result")
+            .and_then(|x| x.get("tracks"))
+            .and_then(|x| x.as_object())
+            .context("tracks is not an object")?
+        {
+            let mut track = TrackInfo::try_from(track_info)?;
+            self.strip_feat(&mut track);
+            tracks.insert(track_id.clone(), track);
+        }
+
+        Ok(tracks)
+    }
+
+    /// Prints track listings of releases to stdout
+    fn list_releases(&self, release_ids: &[String]) -> anyhow::Result<()> {
+        let releases = self
+            .get_releases_info(release_ids)
+            .context("Failed to get releases metadata")?;
+        let track_ids = releases
+            .values()
+            .flat_map(|release_info| release_info.track_ids.clone())
+            .collect::<Vec<_>>();
+        let metadata = self
+            .fetch_tracks_metadata(&track_ids)
+            .context("Failed to get tracks metadata")?;
+
+        for release_info in release_ids
+            .iter()
+            .filter_map(|release_id| releases.get(release_id))
+        {
+            println!(
+                "{} - {} ({})",
+                release_info.author,
+                release_info.album,
+                release_info.date.chars().take(4).collect::<String>()
+            );
+            for track_info in release_info
+                .track_ids
+                .iter()
+                .filter_map(|track_id| metadata.get(track_id))
+            {
+                let duration = track_info.duration.map_or_else(
+                    || String::from("-:--"),
+                    |x| format!("{}:{:02}", x / 60, x % 60),
+                );
+                let flac = if track_info.has_flac { "FLAC" } else { "" };
+                println!(
+                    "{:>3}. {} [{duration}] {flac}",
+                    track_info.number, track_info.name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn strip_feat(&self, track_info: &mut TrackInfo) {
+        if matches!(self.strip_feat, StripFeat::None) {
+            return;
+        }
+        let (title, featured) = split_feat(&track_info.name);
+        let Some(featured) = featured else {
+            return;
+        };
+        tracing::debug!(
+            "Stripping featured artists {featured} from title {}",
+            track_info.name
+        );
+        if matches!(self.strip_feat, StripFeat::MoveToArtist)
+            && !track_info
+                .author
+                .to_lowercase()
+                .contains(&featured.to_lowercase())
+        {
+            track_info.author = format!("{}, {featured}", track_info.author);
+            if !track_info.artists.is_empty() {
+                track_info.artists.push(featured);
+            }
+        }
+        track_info.name = title;
+    }
+
+    fn get_tracks_links(
+        &self,
+        track_ids: &[String],
+        metadata: &HashMap<String, TrackInfo>,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        let fetch = |track_id: &str| {
+            let track_info =
+                metadata.get(track_id).context("no track metadata")?;
+            self.fetch_track_link(track_id, &self.track_quality(track_info))
+        };
+        tracing::info!("Getting download urls in {} quality", self.quality);
+
+        let Some(workers) = self.parallel_links else {
+            let mut urls = HashMap::new();
+            for track_id in track_ids {
+                urls.insert(track_id.clone(), fetch(track_id)?);
+                std::thread::sleep(self.pause_between_getting_track_links);
+            }
+            return Ok(urls);
+        };
+
+        let queue = Mutex::new(track_ids.iter());
+        // pause is shared by all workers
+        let limiter =
+            RateLimiter::with_interval(self.pause_between_getting_track_links);
+        std::thread::scope(|scope| {
+            let handles = (0..workers.get())
+                .map(|_| {
+                    scope.spawn(|| -> anyhow::Result<Vec<(String, String)>> {
+                        let mut urls = Vec::new();
+                        loop {
+                            let next = queue
+                                .lock()
+                                .expect("links queue lock is poisoned")
+                                .next();
+                            let Some(track_id) = next else {
+                                break;
+                            };
+                            limiter.acquire();
+                            match fetch(track_id) {
+                                Ok(url) => urls.push((track_id.clone(), url)),
+                                Err(e) => {
+                                    // stop other workers
+                                    queue
+                                        .lock()
+                                        .expect("links queue lock is poisoned")
+                                        .by_ref()
+                                        .for_each(drop);
+                                    return Err(e);
+                                },
+                            }
+                        }
+                        Ok(urls)
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let mut urls = HashMap::new();
+            for handle in handles {
+                urls.extend(
+                    handle
+                        .join()
+                        .unwrap_or_else(|e| std::panic::resume_unwind(e))?,
+                );
+            }
+            Ok(urls)
+        })
+    }
+
+    fn fetch_track_link(
+        &self,
+        track_id: &str,
+        quality: &Quality,
+    ) -> anyhow::Result<String> {
+        let response = self
+            .send(&self.metadata_request(ZVUK_DOWNLOAD_URL).query(&[
+                ("quality", quality.to_string().as_str()),
+                ("id", track_id),
+            ]))
+            .context("Failed to download track links")?;
+
+        let body =
+            parse_json(response).context("Failed to prase track links")?;
+        tracing::trace!("{ZVUK_DOWNLOAD_URL} response: {body:#?}");
+
+        parse_stream(
+            body.get("result")
+                .and_then(|x| x.get("stream"))
+                .context("no stream in track links")?,
+            quality,
+        )
+    }
+
+    /// Quality to download the track in
+    fn track_quality(&self, track_info: &TrackInfo) -> Quality {
+        match self.quality {
+            Quality::Auto | Quality::Flac if track_info.has_flac => {
+                Quality::Flac
+            },
+            Quality::Auto => Quality::MP3High,
+            Quality::Flac
+                if self.quality_fallback == QualityFallback::Allow =>
+            {
+                Quality::MP3High
+            },
+            ref quality => quality.clone(),
+        }
+    }
+
+    /// Writes track audio to stdout without tagging it. Nothing is written
+    /// if track is skipped
+    fn stream_track(&self, track_id: &str) -> anyhow::Result<()> {
+        let quality = match self.quality {
+            Quality::Auto | Quality::Flac => {
+                let metadata = self
+                    .get_tracks_metadata(&[track_id.to_owned()])
+                    .context("Failed to get track metadata")?;
+                let Some(track_info) = metadata.get(track_id) else {
+                    if self
+                        .skipped
+                        .lock()
+                        .expect("skipped lock is poisoned")
+                        .contains_key(track_id)
+                    {
+                        return Ok(());
+                    }
+                    return Err(anyhow::anyhow!("Track not found"));
+                };
+                self.track_quality(track_info)
+            },
+            ref quality => quality.clone(),
+        };
+        let url = self
+            .fetch_track_link(track_id, &quality)
+            .context("Failed to get track download link")?;
+
+        tracing::info!("Streaming track id={track_id} to stdout");
+        let mut response = self
+            .with_retries(|| self.send_download(&url, 0))
+            .context("Failed to download track")?;
+        response
+            .copy_to(&mut std::io::stdout().lock())
+            .context("Failed to write track to stdout")?;
+        Ok(())
+    }
+
+    fn get_lyrics(
+        &self,
+        track_id: &str,
+        path: &Path,
+    ) -> anyhow::Result<Lyrics> {
+        tracing::info!("Getting lyrics for {}", path.display());
+        let response = self
+            .send(
+                &self
+                    .metadata_request(ZVUK_LYRICS_URL)
+                    .query(&[("track_id", track_id)]),
+            )
+            .context("Failed to download lyrics")?;
+        let body = parse_json(response).context("Failed to parse lyrics")?;
+        tracing::trace!("{ZVUK_LYRICS_URL} response: {body:#?}");
+
+        parse_lyrics(&body)
+    }
+
+    /// Downloads album cover and returns its path. Extension of `path` is
+    /// replaced with the real format of the cover
+    fn download_cover(
+        &self,
+        url: Option<&str>,
+        path: &Path,
+    ) -> anyhow::Result<PathBuf> {
+        let lock = Arc::clone(
+            self.cover_locks
+                .lock()
+                .expect("cover locks lock is poisoned")
+                .entry(path.to_path_buf())
+                .or_default(),
+        );
+        let _guard = lock.lock().expect("cover lock is poisoned");
+        if let Some(cover_file) = &self.cover_file {
+            return self.copy_cover_file(cover_file, path);
+        }
+        if let Some(existing) = existing_cover(path)? {
+            if !self.no_touch_existing_cover {
+                self.resize_cover_file(&existing)?;
+            }
+            return Ok(existing);
+        }
+
+        let url = url.context("No cover URL")?;
+        let data = self.download_cover_data(url)?;
+        let path = path.with_extension(cover_extension(&data));
+        tracing::info!("Saving cover {}", path.display());
+        std::fs::write(&path, &data)?;
+        self.resize_cover_file(&path)?;
+        if self.cover_dedupe {
+            let hash = cover_hash(&data);
+            let mut cover_hashes = self
+                .cover_hashes
+                .lock()
+                .expect("cover hashes lock is poisoned");
+            match cover_hashes.get(&hash) {
+                // hashes can collide, so processed covers are compared too
+                Some(existing) if same_contents(existing, &path)? => {
+                    tracing::debug!(
+                        "Cover {} is the same as {}, linking it",
+                        path.display(),
+                        existing.display()
+                    );
+                    std::fs::remove_file(&path)?;
+                    link_file(existing, &path)?;
+                },
+                Some(_) => {},
+                None => {
+                    cover_hashes.insert(hash, path.clone());
+                },
+            }
+        }
+        Ok(path)
+    }
+
+    /// Copies `--cover-file` to `path` replacing existing cover. Cover is
+    /// copied only once per run for every album
+    fn copy_cover_file(
+        &self,
+        cover_file: &Path,
+        path: &Path,
+    ) -> anyhow::Result<PathBuf> {
+        let copied = self
+            .copied_covers
+            .lock()
+            .expect("copied covers lock is poisoned")
+            .get(path)
+            .cloned();
+        if let Some(copied) = copied {
+            return Ok(copied);
+        }
+
+        let data = std::fs::read(cover_file).with_context(|| {
+            format!("Failed to copy cover {}", cover_file.display())
+        })?;
+        let target = path.with_extension(cover_extension(&data));
+        for extension in COVER_EXTENSIONS {
+            let existing = path.with_extension(extension);
+            if existing != target && existing.try_exists()? {
+                tracing::debug!("Removing cover {}", existing.display());
+                std::fs::remove_file(&existing)?;
+            }
+        }
+        tracing::info!(
+            "Copying cover {} to {}",
+            cover_file.display(),
+            target.display()
+        );
+        std::fs::write(&target, data)?;
+        self.resize_cover_file(&target)?;
+        self.copied_covers
+            .lock()
+            .expect("copied covers lock is poisoned")
+            .insert(path.to_path_buf(), target.clone());
+        Ok(target)
+    }
+
+    /// Downloads cover for the track and returns its path. Returns `None`
+    /// if cover is neither saved nor embedded or it can't be downloaded
+    fn track_cover(
+        &self,
+        folder: &Path,
+        track_info: &TrackInfo,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        if !self.save_cover && (!self.embed_cover || self.no_tag) {
+            return Ok(None);
+        }
+        let cover_path = self.cover_path(folder, track_info);
+        let cover_path = if track_info.image.is_none()
+            && self.cover_file.is_none()
+            && existing_cover(&cover_path)?.is_none()
+        {
+            tracing::warn!(
+                "Track id={} has no cover, skipping it",
+                track_info.track_id
+            );
+            None
+        } else {
+            match self.download_cover(track_info.image.as_deref(), &cover_path)
+            {
+                Ok(cover_path) => Some(cover_path),
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to download and process album cover for track id={}, skipping it: {e:#}",
+                        track_info.track_id
+                    );
+                    None
+                },
+            }
+        };
+        Ok(match cover_path {
+            Some(cover_path)
+                if std::fs::metadata(&cover_path)?.len()
+                    < self.min_cover_size =>
+            {
+                tracing::warn!(
+                    "Cover {} is smaller than {} bytes, treating it as missing",
+                    cover_path.display(),
+                    self.min_cover_size
+                );
+                None
+            },
+            cover_path => cover_path,
+        })
+    }
+
+    /// Returns path of the album cover with `jpg` extension. If cover is
+    /// only embedded, it is saved to temporary folder and removed at the end
+    fn cover_path(&self, folder: &Path, track_info: &TrackInfo) -> PathBuf {
+        if self.save_cover {
+            return folder.join("cover.jpg");
+        }
+        let path = self
+            .temp_dir
+            .clone()
+            .unwrap_or_else(std::env::temp_dir)
+            .join(format!("zvuk-dl-cover-{}.jpg", track_info.release_id));
+        self.temp_covers
+            .lock()
+            .expect("temp covers lock is poisoned")
+            .insert(path.clone());
+        path
+    }
+
+    /// Removes covers that were downloaded only for embedding
+    fn remove_temp_covers(&self) {
+        let paths = std::mem::take(
+            &mut *self
+                .temp_covers
+                .lock()
+                .expect("temp covers lock is poisoned"),
+        );
+        for path in paths {
+            for extension in COVER_EXTENSIONS {
+                let path = path.with_extension(extension);
+                if !path.exists() {
+                    continue;
+                }
+                if let Err(e) = std::fs::remove_file(&path) {
+                    tracing::debug!(
+                        "Failed to remove {}: {e}",
+                        path.display()
+                    );
+                }
+            }
+        }
+    }
+
+    /// Downloads cover in `--cover-size` size. If server doesn't have
+    /// this size, cover is downloaded in default size
+    fn download_cover_data(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+        let download = |url: &str| {
+            self.with_retries(|| Ok(self.send_download(url, 0)?.bytes()?))
+        };
+        match download(&cover_url(url, &self.cover_size)) {
+            Err(e)
+                if self.cover_size != ZVUK_DEFAULT_COVER_SIZE
+                    && e.downcast_ref::<HttpStatus>()
+                        .is_some_and(|x| x.status.is_client_error()) =>
+            {
+                tracing::warn!(
+                    "No cover in {} size, downloading default size: {e:#}",
+                    self.cover_size
+                );
+                Ok(download(&cover_url(url, ZVUK_DEFAULT_COVER_SIZE))?.into())
+            },
+            result => Ok(result?.into()),
+        }
+    }
+
+    fn resize_cover_file(&self, path: &Path) -> anyhow::Result<()> {
+        if self.resize_cover
+            && std::fs::metadata(path)?.len() > self.resize_cover_limit
+        {
+            tracing::debug!("Resizing cover {}", path.display());
+
+            if self.resize_command == ZVUK_BUILTIN_RESIZE_COMMAND {
+                let data = std::fs::read(path)?;
+                let resized = resize_image(&data, self.resize_cover_limit)?;
+                std::fs::write(path, resized)?;
+                return Ok(());
+            }
+
+            let path_str =
+                path.to_str().context("Failed to convert path to str")?;
+            let command_str = self
+                .resize_command
+                .split_whitespace()
+                .map(|x| {
+                    x.replace("{source}", path_str)
+                        .replace("{target}", path_str)
+                })
+                .collect::<Vec<String>>();
+            let (command, args) = command_str
+                .split_first()
+                .context("Failed to parse resize command")?;
+
+            let status = std::process::Command::new(command)
+                .args(args)
+                .status()
+                .with_context(|| {
+                    format!(
+                        "Failed to run resize command {command}, use --resize-command={ZVUK_BUILTIN_RESIZE_COMMAND} if it is not installed"
+                    )
+                })?;
+            if !status.success() {
+                return Err(anyhow::anyhow!("Failed to resize cover"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates album folder for the release and returns its path
+    fn release_folder(
+        &self,
+        release_id: &str,
+        release_info: &ReleaseInfo,
+    ) -> anyhow::Result<PathBuf> {
+        create_release_folder(
+            &long_path(&self.release_folder_path(release_info))?,
+            release_id,
+            &self.dir_template,
+        )
+    }
+
+    /// Renders `--dir-template` for the release
+    fn release_folder_path(&self, release_info: &ReleaseInfo) -> PathBuf {
+        render_template(
+            &self.dir_template,
+            &[
+                ("artist", &release_info.author),
+                ("album", &release_info.album),
+                (
+                    "year",
+                    &release_info.date.chars().take(4).collect::<String>(),
+                ),
+                ("label", &release_info.label),
+            ],
+        )
+    }
+
+    /// Renders `--file-template` for the track. Path can contain folders
+    fn track_filename(&self, track_info: &TrackInfo) -> PathBuf {
+        self.track_filename_with_quality(
+            track_info,
+            &self.track_quality(track_info),
+        )
+    }
+
+    fn track_filename_with_quality(
+        &self,
+        track_info: &TrackInfo,
+        quality: &Quality,
+    ) -> PathBuf {
+        let ext = quality.extension();
+        let filename = render_template(
+            &self.file_template,
+            &[
+                ("track", &format!("{:02}", track_info.number)),
+                ("title", &track_info.name),
+                ("artist", &track_info.author),
+                ("album", &track_info.album),
+                ("genre", &track_info.genre),
+                ("ext", &ext),
+            ],
+        );
+        if !self.quality_in_filename {
+            return filename;
+        }
+        let label = quality.label();
+        let filename = filename.to_string_lossy();
+        PathBuf::from(filename.strip_suffix(&format!(".{ext}")).map_or_else(
+            || format!("{filename} [{label}]"),
+            |stem| format!("{stem} [{label}].{ext}"),
+        ))
+    }
+
+    /// Returns path of already downloaded track that should not be
+    /// downloaded again. Empty files are not considered downloaded
+    fn existing_track(
+        &self,
+        folder: &Path,
+        track_info: &TrackInfo,
+    ) -> Option<PathBuf> {
+        if self.overwrite {
+            return None;
+        }
+        if self.skip_by_id {
+            let path = self
+                .track_files
+                .get_or_init(|| {
+                    tracing::info!(
+                        "Looking for downloaded tracks by track id"
+                    );
+                    scan_track_ids(Path::new("."))
+                })
+                .get(&track_info.track_id);
+            if path.is_some() {
+                return path.cloned();
+            }
+        }
+        let qualities = match self.skip_existing {
+            SkipExisting::Any => Quality::value_variants()
+                .iter()
+                .filter(|x| !matches!(x, Quality::Auto))
+                .cloned()
+                .collect(),
+            SkipExisting::SameQuality => vec![self.track_quality(track_info)],
+        };
+        qualities
+            .iter()
+            .map(|quality| {
+                folder.join(
+                    self.track_filename_with_quality(track_info, quality),
+                )
+            })
+            .find(|path| std::fs::metadata(path).is_ok_and(|x| x.len() > 0))
+    }
+
+    /// Remembers downloaded track for album playlist
+    fn save_track(
+        &self,
+        folder: PathBuf,
+        path: PathBuf,
+        track_info: &TrackInfo,
+    ) {
+        if self.write_playlist {
+            self.saved_tracks
+                .lock()
+                .expect("saved tracks lock is poisoned")
+                .push(SavedTrack {
+                    folder,
+                    path,
+                    number: track_info.number,
+                    duration: track_info.duration,
+                    artist: track_info.author.clone(),
+                    title: track_info.name.clone(),
+                });
+        }
+    }
+
+    /// Creates folder for the playlist and returns its path
+    fn playlist_folder(playlist: &PlaylistInfo) -> anyhow::Result<PathBuf> {
+        let folder = long_path(&PathBuf::from(truncate_filename(
+            &sanitize_path(&playlist.title),
+        )))?;
+        std::fs::create_dir_all(&folder).with_context(|| {
+            format!("Failed to create folder {}", folder.display())
+        })?;
+        Ok(folder)
+    }
+
+    /// Downloads track to `path`. Existing partially downloaded file is
+    /// resumed if server supports range requests
+    fn download_track_file(
+        &self,
+        url: &str,
+        track_id: &str,
+        path: &Path,
+    ) -> anyhow::Result<()> {
+        let offset = std::fs::metadata(path).map_or(0, |x| x.len());
+        let response = match self.send_download(url, offset) {
+            Err(e)
+                if offset > 0
+                    && e.downcast_ref::<HttpStatus>().is_some_and(|x| {
+                        x.status == StatusCode::RANGE_NOT_SATISFIABLE
+                    }) =>
+            {
+                tracing::debug!(
+                    "Can't resume {}, downloading it again",
+                    path.display()
+                );
+                self.send_download(url, 0)
+            },
+            response => response,
+        }
+        .context("Failed to download track")?;
+
+        let offset = if response.status() == StatusCode::PARTIAL_CONTENT {
+            tracing::info!("Resuming {} from {offset} bytes", path.display());
+            offset
+        } else {
+            0
+        };
+        let total = response.content_length().map(|x| x + offset);
+        let host = response.url().host_str().unwrap_or_default().to_owned();
+        let file = if offset > 0 {
+            std::fs::OpenOptions::new().append(true).open(path)
+        } else {
+            std::fs::File::create(path)
+        }
+        .context("Failed to save track on disk")?;
+        let mut file = std::io::BufWriter::new(file);
+        std::io::copy(
+            &mut ProgressReader::new(
+                response,
+                self.progress.as_ref(),
+                track_id,
+                total,
+            )
+            .with_offset(offset),
+            &mut file,
+        )
+        .with_context(|| format!("Failed to read track data from {host}"))?;
+        std::io::Write::flush(&mut file)
+            .context("Failed to save track on disk")?;
+        Ok(())
+    }
+
+    fn get_and_save_track(
+        &self,
+        url: &str,
+        track_info: &TrackInfo,
+        release_info: &ReleaseInfo,
+        total_tracks: Option<u32>,
+        playlist: Option<&PlaylistInfo>,
+    ) -> anyhow::Result<()> {
+        let folder = match playlist {
+            Some(playlist) => Self::playlist_folder(playlist)?,
+            None => {
+                self.release_folder(&track_info.release_id, release_info)?
+            },
+        };
+
+        if let Some(filepath) = self.existing_track(&folder, track_info) {
+            self.skip_file(
+                &track_info.track_id,
+                SkipReason::Exists,
+                Some(&filepath),
+            );
+            self.save_track(folder, filepath, track_info);
+            return Ok(());
+        }
+
+        let cover_path = self.track_cover(&folder, track_info)?;
+
+        let filepath = folder.join(self.track_filename(track_info));
+
+        tracing::info!("Downloading {}", filepath.display());
+        self.progress.on_track_started(
+            &track_info.track_id,
+            &filepath,
+            &self.track_quality(track_info).to_string(),
+        );
+
+        let download_path = match &self.temp_dir {
+            Some(temp_dir) => {
+                std::fs::create_dir_all(temp_dir).with_context(|| {
+                    format!("Failed to create folder {}", temp_dir.display())
+                })?;
+                temp_dir.join(format!(
+                    "{}.{}.part",
+                    track_info.track_id,
+                    self.track_quality(track_info).extension()
+                ))
+            },
+            None => {
+                let mut path = filepath.clone().into_os_string();
+                path.push(".part");
+                PathBuf::from(path)
+            },
+        };
+        self.with_retries(|| {
+            self.download_track_file(url, &track_info.track_id, &download_path)
+        })?;
+
+        if self.reject_flac_format(
+            &track_info.track_id,
+            &download_path,
+            &self.track_quality(track_info),
+        )? {
+            return Ok(());
+        }
+
+        move_file(&download_path, &filepath)
+            .context("Failed to move downloaded track")?;
+
+        if !self.no_tag {
+            self.write_tags(
+                &filepath,
+                &self.track_quality(track_info),
+                cover_path.as_deref(),
+                track_info,
+                release_info,
+                total_tracks,
+            )?;
+        }
+        self.progress.on_track_done(&track_info.track_id, &filepath);
+        self.downloaded.fetch_add(1, Ordering::Relaxed);
+        // track could fail in previous retry round
+        self.failures
+            .lock()
+            .expect("failures lock is poisoned")
+            .remove(&track_info.track_id);
+        self.save_track(folder, filepath, track_info);
+
+        Ok(())
+    }
+
+    /// Remembers metadata of tracks for `--metadata-json`
+    fn record_metadata(
+        &self,
+        metadata: &HashMap<String, TrackInfo>,
+        releases: &HashMap<String, ReleaseInfo>,
+    ) {
+        if self.metadata_json.is_none() {
+            return;
+        }
+        self.resolved_metadata
+            .lock()
+            .expect("resolved metadata lock is poisoned")
+            .extend(metadata.iter().map(|(track_id, track_info)| {
+                (
+                    track_id.clone(),
+                    serde_json::json!({
+                        "track": track_info,
+                        "release": releases.get(&track_info.release_id),
+                        "quality": self.track_quality(track_info).to_string(),
+                    }),
+                )
+            }));
+    }
+
+    /// Writes metadata of all requested tracks to JSON file
+    fn write_metadata_json(&self, path: &Path) -> anyhow::Result<()> {
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        serde_json::to_writer_pretty(
+            std::io::BufWriter::new(file),
+            &*self
+                .resolved_metadata
+                .lock()
+                .expect("resolved metadata lock is poisoned"),
+        )
+        .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Writes .m3u8 playlist into every folder with downloaded tracks
+    fn write_playlists(&self) -> anyhow::Result<()> {
+        let saved_tracks = std::mem::take(
+            &mut *self
+                .saved_tracks
+                .lock()
+                .expect("saved tracks lock is poisoned"),
+        );
+        let mut folders: BTreeMap<PathBuf, Vec<SavedTrack>> = BTreeMap::new();
+        for track in saved_tracks {
+            folders.entry(track.folder.clone()).or_default().push(track);
+        }
+
+        for (folder, mut tracks) in folders {
+            tracks.sort_by_key(|track| track.number);
+            let mut playlist = String::from("#EXTM3U\n");
+            for track in &tracks {
+                let path =
+                    track.path.strip_prefix(&folder).unwrap_or(&track.path);
+                let _ = writeln!(
+                    playlist,
+                    "#EXTINF:{},{} - {}\n{}",
+                    track
+                        .duration
+                        .map_or(-1, |x| i64::try_from(x).unwrap_or(-1)),
+                    track.artist,
+                    track.title,
+                    path.display()
+                );
+            }
+            let name = folder.file_name().map_or_else(
+                || String::from("playlist"),
+                |x| x.to_string_lossy().into_owned(),
+            );
+            let path = folder.join(format!("{name}.m3u8"));
+            tracing::info!("Writing playlist {}", path.display());
+            std::fs::write(&path, playlist).with_context(|| {
+                format!("Failed to write playlist {}", path.display())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Returns why downloaded FLAC doesn't meet `--min-bit-depth` and
+    /// `--min-sample-rate`, `None` if it does
+    fn check_flac_format(
+        &self,
+        path: &Path,
+        quality: &Quality,
+    ) -> anyhow::Result<Option<String>> {
+        if !matches!(quality, Quality::Flac)
+            || (self.min_bit_depth.is_none() && self.min_sample_rate.is_none())
+        {
+            return Ok(None);
+        }
+        let tag = metaflac::Tag::read_from_path(path)
+            .context("Failed to read FLAC stream info")?;
+        let info = tag.get_streaminfo().context("FLAC has no stream info")?;
+        if let Some(min_bit_depth) = self.min_bit_depth {
+            if info.bits_per_sample < min_bit_depth {
+                return Ok(Some(format!(
+                    "FLAC bit depth {} is lower than {min_bit_depth}",
+                    info.bits_per_sample
+                )));
+            }
+        }
+        if let Some(min_sample_rate) = self.min_sample_rate {
+            if info.sample_rate < min_sample_rate {
+                return Ok(Some(format!(
+                    "FLAC sample rate {} is lower than {min_sample_rate}",
+                    info.sample_rate
+                )));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Removes downloaded track and skips it if its FLAC format is lower
+    /// than requested. Returns `true` if track is skipped
+    fn reject_flac_format(
+        &self,
+        track_id: &str,
+        path: &Path,
+        quality: &Quality,
+    ) -> anyhow::Result<bool> {
+        let problem = match self.check_flac_format(path, quality) {
+            Ok(None) => return Ok(false),
+            Ok(Some(problem)) => problem,
+            Err(e) => {
+                std::fs::remove_file(path).with_context(|| {
+                    format!("Failed to remove {}", path.display())
+                })?;
+                return Err(e);
+            },
+        };
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove {}", path.display()))?;
+        tracing::info!("Track id={track_id}: {problem}");
+        self.skip(track_id, SkipReason::BelowMinFormat);
+        Ok(true)
+    }
+
+    fn map_genre(&self, genre: &str) -> String {
+        if self.genre_map.is_empty() {
+            return genre.to_owned();
+        }
+        let mut genres: Vec<&str> = Vec::new();
+        for genre in genre.split(", ") {
+            let genre = self
+                .genre_map
+                .get(&genre.to_lowercase())
+                .map_or(genre, String::as_str);
+            if !genres.contains(&genre) {
+                genres.push(genre);
+            }
+        }
+        genres.join(", ")
+    }
+
+    /// Reads cover file once and reuses it for all tracks of the album
+    fn read_cover(&self, cover_path: &Path) -> anyhow::Result<Arc<[u8]>> {
+        let mut covers =
+            self.covers.lock().expect("covers cache lock is poisoned");
+        if let Some(data) = covers.get(cover_path) {
+            return Ok(Arc::clone(data));
+        }
+        let data: Arc<[u8]> = std::fs::read(cover_path)?.into();
+        covers.insert(cover_path.to_path_buf(), Arc::clone(&data));
+        drop(covers);
+        Ok(data)
+    }
+
+    /// Writes tags to track file. `quality` is the quality of the file and
+    /// defines the tag format
+    fn write_tags(
+        &self,
+        filepath: &Path,
+        quality: &Quality,
+        cover_path: Option<&Path>,
+        track_info: &TrackInfo,
+        release_info: &ReleaseInfo,
+        total_tracks: Option<u32>,
+    ) -> anyhow::Result<()> {
+        let mut tags: Box<dyn AudioTag + Send + Sync> = match quality {
+            Quality::Auto | Quality::Flac => FlacTag::read_from_path(filepath)
+                .map_or_else(
+                    |_| {
+                        tracing::trace!("Failed to read FLAC tag from file");
+                        Box::new(FlacTag::new())
+                    },
+                    Box::new,
+                ),
+            Quality::MP3High | Quality::MP3Mid => {
+                Id3v2Tag::read_from_path(filepath).map_or_else(
+                    |_| {
+                        tracing::trace!("Failed to read ID3v2 tag from file");
+                        Box::new(Id3v2Tag::new())
+                    },
+                    Box::new,
+                )
+            },
+        };
+
+        tags.set_artist(&track_info.author);
+        tags.set_title(&track_info.name);
+        tags.set_album_title(&release_info.album);
+        tags.set_album_artist(&release_info.author);
+        tags.set_track_number(track_info.number.try_into()?);
+        if let Some(total_tracks) = total_tracks {
+            tags.set_total_tracks(total_tracks.try_into()?);
+        }
+        tags.set_genre(&self.map_genre(&track_info.genre));
+
+        if let Ok(date) =
+            NaiveDate::parse_from_str(&release_info.date, "%Y%m%d")
+        {
+            tags.set_date(id3::Timestamp {
+                year: date.year(),
+                month: u8::try_from(date.month()).ok(),
+                day: u8::try_from(date.day()).ok(),
+                hour: None,
+                minute: None,
+                second: None,
+            });
+            tags.set_year(date.year());
+        }
+
+        if let Some(cover_path) = cover_path.filter(|_| self.embed_cover) {
+            let data = self
+                .read_cover(cover_path)
+                .context("Failed to read cover file for embedding")?;
+            match cover_mime_type(&data) {
+                Ok(mime_type) => tags.set_album_cover(Picture {
+                    mime_type,
+                    data: &data,
+                }),
+                Err(e) => tracing::warn!(
+                    "Cover {} is not a valid image, not embedding it: {e:#}",
+                    cover_path.display()
+                ),
+            }
+        }
+
+        let lyrics = if self.download_lyrics && track_info.lyrics {
+            let mut lyrics = match self
+                .get_lyrics(&track_info.track_id, filepath)
+            {
+                Ok(lyrics) => lyrics,
+                Err(e) if e.is::<AuthFailed>() => {
+                    return Err(e.context("Failed to get lyrics"));
+                },
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to get lyrics for {}, writing tags without them: {e:#}",
+                        filepath.display()
+                    );
+                    Lyrics {
+                        kind: LyricsKind::Lyrics,
+                        text: String::new(),
+                    }
+                },
+            };
+            if is_lyrics_placeholder(&lyrics.text, &self.lyrics_placeholders) {
+                tracing::warn!(
+                    "Lyrics for {} is a placeholder, skipping it",
+                    filepath.display()
+                );
+                lyrics.text.clear();
+            } else if lyrics.text.is_empty() {
+                tracing::warn!("No lyrics for {}", filepath.display());
+            }
+            self.write_lrc(filepath, lyrics)?
+        } else {
+            None
+        };
+
+        match quality {
+            Quality::Auto | Quality::Flac => {
+                Self::write_extra_tags_flac(
+                    filepath,
+                    track_info,
+                    release_info,
+                    tags,
+                    lyrics.as_ref(),
+                )?;
+            },
+            Quality::MP3High | Quality::MP3Mid => {
+                Self::write_extra_tags_mp3(
+                    filepath,
+                    track_info,
+                    release_info,
+                    tags,
+                    lyrics.as_ref(),
+                )?;
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Writes synchronized lyrics to .lrc file if requested and returns
+    /// lyrics that should be embedded
+    fn write_lrc(
+        &self,
+        filepath: &Path,
+        lyrics: Lyrics,
+    ) -> anyhow::Result<Option<Lyrics>> {
+        if !matches!(lyrics.kind, LyricsKind::Subtitle)
+            || lyrics.text.is_empty()
+            || self.lyrics_format == LyricsFormat::Embed
+        {
+            return Ok(Some(lyrics));
+        }
+        let path = filepath.with_extension("lrc");
+        tracing::info!("Writing lyrics {}", path.display());
+        std::fs::write(&path, &lyrics.text)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok((self.lyrics_format == LyricsFormat::Both).then_some(lyrics))
+    }
+
+    fn write_extra_tags_flac(
+        filepath: &Path,
+        track_info: &TrackInfo,
+        release_info: &ReleaseInfo,
+        tags: Box<dyn AudioTag + Send + Sync>,
+        lyrics: Option<&Lyrics>,
+    ) -> anyhow::Result<()> {
+        let mut flactag: metaflac::Tag = tags.into();
+        let vorbis_tags = flactag.vorbis_comments_mut();
+
+        vorbis_tags.set("COPYRIGHT", vec![&release_info.label]);
+        if track_info.artists.len() > 1 {
+            vorbis_tags.set("ARTIST", track_info.artists.clone());
+        }
+        if let Some(isrc) = &track_info.isrc {
+            vorbis_tags.set("ISRC", vec![isrc]);
+        }
+        if !track_info.composers.is_empty() {
+            vorbis_tags.set("COMPOSER", track_info.composers.clone());
+        }
+        if let Some(bpm) = track_info.bpm {
+            vorbis_tags.set("BPM", vec![bpm.to_string()]);
+        }
+        if track_info.explicit {
+            vorbis_tags.set("ITUNESADVISORY", vec!["1"]);
+        }
+        vorbis_tags.set("RELEASE_ID", vec![&track_info.release_id]);
+        vorbis_tags.set("TRACK_ID", vec![&track_info.track_id]);
+
+        if let Some(lyrics) = lyrics {
+            if !lyrics.text.is_empty() {
+                vorbis_tags.set_lyrics(vec![&lyrics.text]);
+            }
+        }
+
+        let mut tags: FlacTag = flactag.into();
+        tags.write_to_path(
+            filepath.to_str().context("filepath is not valid string")?,
+        )
+        .context("Failed to write tags to file")?;
+        Ok(())
+    }
+
+    fn write_extra_tags_mp3(
+        filepath: &Path,
+        track_info: &TrackInfo,
+        release_info: &ReleaseInfo,
+        tags: Box<dyn AudioTag + Send + Sync>,
+        lyrics: Option<&Lyrics>,
+    ) -> anyhow::Result<()> {
+        let mut mp3tags: id3::Tag = tags.into();
+
+        mp3tags.set_text("TCOP", &release_info.label);
+        if track_info.artists.len() > 1 {
+            mp3tags.set_text_values("TPE1", &track_info.artists);
+        }
+        if let Some(isrc) = &track_info.isrc {
+            mp3tags.set_text("TSRC", isrc);
+        }
+        if !track_info.composers.is_empty() {
+            mp3tags.set_text_values("TCOM", &track_info.composers);
+        }
+        if let Some(bpm) = track_info.bpm {
+            mp3tags.set_text("TBPM", bpm.to_string());
+        }
+        if track_info.explicit {
+            mp3tags.add_frame(frame::ExtendedText {
+                description: String::from("ITUNESADVISORY"),
+                value: String::from("1"),
+            });
+        }
+        mp3tags.add_frame(frame::ExtendedText {
+            description: String::from("TRACK_ID"),
+            value: track_info.track_id.clone(),
+        });
+
+        if let Some(lyrics) = lyrics {
+            if !lyrics.text.is_empty() {
+                mp3tags.add_frame(frame::Lyrics {
+                    lang: String::new(),
+                    description: String::new(),
+                    text: lyrics.text.clone(),
+                });
+            }
+        }
+
+        let mut tags: Id3v2Tag = mp3tags.into();
+        tags.write_to_path(
+            filepath.to_str().context("filepath is not valid string")?,
+        )
+        .context("Failed to write tags to file")?;
+        Ok(())
+    }
+}
+
+/// Ids parsed from requested URLs
+#[derive(Debug, Default)]
+struct RequestedIds {
+    release_ids: Vec<String>,
+    track_ids: Vec<String>,
+    playlist_ids: Vec<String>,
+    artist_ids: Vec<String>,
+    /// URLs that don't look like zvuk.com URLs
+    unknown_urls: Vec<String>,
+}
+
+impl RequestedIds {
+    fn parse(urls: &[String]) -> Self {
+        let mut ids = Self::default();
+        for url in urls {
+            let url = &strip_locale(url);
+            if let Some(url) = url.strip_prefix(ZVUK_RELEASE_PREFIX) {
+                ids.release_ids.push(url.to_owned());
+            } else if let Some(url) = url.strip_prefix(ZVUK_TRACKS_PREFIX) {
+                ids.track_ids.push(url.to_owned());
+            } else if let Some(url) = url.strip_prefix(ZVUK_PLAYLIST_PREFIX) {
+                ids.playlist_ids.push(url.to_owned());
+            } else if let Some(url) = url.strip_prefix(ZVUK_ARTIST_PREFIX) {
+                ids.artist_ids.push(url.to_owned());
+            } else {
+                ids.unknown_urls.push(url.clone());
+            }
+        }
+        ids
+    }
+
+    /// Returns id of the only requested track for `--stdout`
+    fn single_track(&self) -> anyhow::Result<&str> {
+        match self.track_ids.as_slice() {
+            [track_id]
+                if self.release_ids.is_empty()
+                    && self.playlist_ids.is_empty()
+                    && self.artist_ids.is_empty()
+                    && self.unknown_urls.is_empty() =>
+            {
+                Ok(track_id.as_str())
+            },
+            _ => Err(anyhow::anyhow!(
+                "--stdout option requires exactly one track URL"
+            )),
+        }
+    }
+}
+
+pub fn download(config: &Config) -> anyhow::Result<()> {
+    let ids = RequestedIds::parse(&config.urls);
+
+    let progress: Box<dyn Progress> = if config.json_lines {
+        Box::new(JsonLinesProgress::new(std::io::stdout()))
+    } else {
+        Box::new(LogProgress)
+    };
+    let client = Client::new(config, progress)?;
+
+    if config.stdout {
+        return client.stream_track(ids.single_track()?);
+    }
+
+    for url in &ids.unknown_urls {
+        tracing::warn!("This doens't look like zvuk.com URL, skipping: {url}");
+    }
+    let RequestedIds {
+        release_ids,
+        track_ids,
+        playlist_ids,
+        artist_ids,
+        ..
+    } = ids;
+
+    if config.list {
+        if !track_ids.is_empty()
+            || !playlist_ids.is_empty()
+            || !artist_ids.is_empty()
+        {
+            tracing::warn!("--list supports only release URLs");
+        }
+        return client.list_releases(&release_ids);
+    }
+
+    if config.rewrite_tags {
+        if !track_ids.is_empty()
+            || !playlist_ids.is_empty()
+            || !artist_ids.is_empty()
+        {
+            tracing::warn!("--rewrite-tags supports only release URLs");
+        }
+        return client.rewrite_tags(&release_ids);
+    }
+
+    if !release_ids.is_empty() {
+        client.download_albums(&release_ids)?;
+    }
+    if !track_ids.is_empty() {
+        client.download_tracks(&track_ids, &HashMap::new(), None)?;
+    }
+    if !playlist_ids.is_empty() {
+        client.download_playlists(&playlist_ids)?;
+    }
+    if !artist_ids.is_empty() {
+        client.download_artists(&artist_ids)?;
+    }
+    if let Some(path) = &config.metadata_json {
+        client.write_metadata_json(path)?;
+    }
+    client.remove_temp_covers();
+    client.log_summary()
+}
+
+/// Parses header in `Name: Value` form
+pub fn parse_header(
+    header: &str,
+) -> anyhow::Result<(HeaderName, HeaderValue)> {
+    let (name, value) = header
+        .split_once(':')
+        .context("header must look like `Name: Value`")?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .with_context(|| format!("invalid header name {:?}", name.trim()))?;
+    let value =
+        HeaderValue::from_str(value.trim()).context("invalid header value")?;
+    Ok((name, value))
+}
+
+/// Parses JSON response of tiny API and checks that it isn't an error
+fn parse_json(response: Response) -> anyhow::Result<serde_json::Value> {
+    let body = response.json::<serde_json::Value>()?;
+    check_api_error(&body)?;
+    Ok(body)
+}
+
+/// Gets lyrics from lyrics response. Missing or `null` lyrics are returned
+/// as empty text
+fn parse_lyrics(body: &serde_json::Value) -> anyhow::Result<Lyrics> {
+    let result = body.get("result").context("no result in lyrics")?;
+    let text = match result.get("lyrics") {
+        None | Some(serde_json::Value::Null) => String::new(),
+        Some(lyrics) => lyrics
+            .as_str()
+            .context("lyrics is not a string")?
+            .to_string(),
+    };
+    let kind =
+        if result.get("type").and_then(|x| x.as_str()) == Some("subtitle") {
+            LyricsKind::Subtitle
+        } else {
+            LyricsKind::Lyrics
+        };
+    Ok(Lyrics { kind, text })
+}
+
+/// Returns error if response has an error instead of result. Errors about
+/// authorization are returned as [`AuthFailed`]
+fn check_api_error(body: &serde_json::Value) -> anyhow::Result<()> {
+    let Some(error) = body.get("error").filter(|x| !x.is_null()) else {
+        return Ok(());
+    };
+    if body.get("result").is_some_and(|x| !x.is_null()) {
+        return Ok(());
+    }
+    if is_auth_error(error) {
+        return Err(anyhow::Error::from(AuthFailed)
+            .context(format!("zvuk.com returned error: {error}")));
+    }
+    Err(anyhow::anyhow!("zvuk.com returned error: {error}"))
+}
+
+/// Checks that API error is about invalid or expired token: its status or
+/// code is 401, or its code or message has one of [`AUTH_ERROR_WORDS`]
+fn is_auth_error(error: &serde_json::Value) -> bool {
+    if ["status", "code"].iter().any(|key| {
+        error
+            .get(key)
+            .and_then(serde_json::Value::as_u64)
+            .is_some_and(|x| x == 401)
+    }) {
+        return true;
+    }
+    let text = match error {
+        serde_json::Value::String(text) => text.to_lowercase(),
+        _ => error.to_string().to_lowercase(),
+    };
+    text.split(|x: char| !x.is_alphanumeric() && x != '_')
+        .any(|word| AUTH_ERROR_WORDS.contains(&word))
+}
+
+/// Gets release ids and types from artist releases GraphQL response
+fn parse_artist_releases(
+    body: &serde_json::Value,
+) -> anyhow::Result<Vec<(String, Option<ReleaseType>)>> {
+    if let Some(errors) = body.get("errors") {
+        return Err(anyhow::anyhow!("GraphQL error: {errors}"));
+    }
+    let releases = body
+        .get("data")
+        .and_then(|x| x.get("getArtists"))
+        .and_then(|x| x.as_array())
+        .and_then(|x| x.first())
+        .context("Artist not found")?
+        .get("releases")
+        .and_then(|x| x.as_array())
+        .context("releases is not an array")?;
+    releases
+        .iter()
+        .map(|release| {
+            let release_id = match release.get("id") {
+                Some(serde_json::Value::String(id)) => id.clone(),
+                Some(serde_json::Value::Number(id)) => id.to_string(),
+                _ => return Err(anyhow::anyhow!("Release has no id")),
+            };
+            let type_ = release
+                .get("type")
+                .and_then(|x| x.as_str())
+                .and_then(ReleaseType::parse);
+            Ok((release_id, type_))
+        })
+        .collect()
+}
+
+/// Whether request that failed with this error is worth retrying
+fn is_retryable(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<HttpStatus>() {
+            return e.status.is_server_error()
+                || e.status == StatusCode::TOO_MANY_REQUESTS;
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_timeout()
+                || e.is_connect()
+                || e.is_request()
+                || e.is_body()
+                || e.is_decode();
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return e
+                .get_ref()
+                .and_then(|x| x.downcast_ref::<reqwest::Error>())
+                .is_some()
+                || matches!(
+                    e.kind(),
+                    std::io::ErrorKind::TimedOut
+                        | std::io::ErrorKind::ConnectionReset
+                        | std::io::ErrorKind::ConnectionAborted
+                        | std::io::ErrorKind::UnexpectedEof
+                );
+        }
+        false
+    })
+}
+
+/// Returns how long to wait before retrying request failed with `error`.
+/// Server can ask to wait with `Retry-After`, otherwise `delay` is used.
+/// Returns `None` if server asks to wait longer than [`MAX_RETRY_AFTER`]
+fn retry_wait(error: &anyhow::Error, delay: Duration) -> Option<Duration> {
+    let retry_after = error
+        .chain()
+        .find_map(|x| x.downcast_ref::<HttpStatus>())
+        .and_then(|x| x.retry_after);
+    match retry_after {
+        Some(retry_after) if retry_after > MAX_RETRY_AFTER => None,
+        retry_after => Some(retry_after.unwrap_or(delay)),
+    }
+}
+
+/// Parses `Retry-After` header value which is either a number of seconds or
+/// an HTTP date
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let date = SystemTime::UNIX_EPOCH
+        + Duration::from_secs(u64::try_from(date.timestamp()).ok()?);
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+/// Gets stream URL for quality from track stream response.
+///
+/// Stream is either a URL string, an object with URL for every quality like
+/// `{"flac": "...", "high": "..."}` or an array of objects like
+/// `[{"quality": "flac", "stream": "..."}]`
+fn parse_stream(
+    stream: &serde_json::Value,
+    quality: &Quality,
+) -> anyhow::Result<String> {
+    let quality = quality.to_string();
+    let url = match stream {
+        serde_json::Value::String(url) => Some(url.as_str()),
+        serde_json::Value::Object(streams) => {
+            streams.get(&quality).and_then(|x| x.as_str())
+        },
+        serde_json::Value::Array(streams) => streams
+            .iter()
+            .find(|x| {
+                x.get("quality").and_then(|x| x.as_str())
+                    == Some(quality.as_str())
+            })
+            .and_then(|x| x.get("stream").or_else(|| x.get("url")))
+            .and_then(|x| x.as_str()),
+        _ => None,
+    };
+    url.filter(|x| !x.is_empty())
+        .map(str::to_owned)
+        .with_context(|| format!("no stream URL for {quality} quality"))
+}
+
+/// Checks that cover can be decoded and returns its MIME type
+fn cover_mime_type(data: &[u8]) -> anyhow::Result<MimeType> {
+    let format = image::guess_format(data).context("Unknown image format")?;
+    let mime_type = match format {
+        image::ImageFormat::Jpeg => MimeType::Jpeg,
+        image::ImageFormat::Png => MimeType::Png,
+        _ => return Err(anyhow::anyhow!("Unsupported image format")),
+    };
+    image::load_from_memory_with_format(data, format)
+        .context("Failed to decode image")?;
+    Ok(mime_type)
+}
+
+/// Puts cover size into cover URL template. Size parameter is removed for
+/// default size
+#[expect(clippy::literal_string_with_formatting_args)]
+fn cover_url(template: &str, size: &str) -> String {
+    if size == ZVUK_DEFAULT_COVER_SIZE {
+        return template.replace("&size={size}&ext=jpg", "");
+    }
+    template.replace("{size}", size)
+}
+
+/// Returns extension for cover file by its format. Covers in formats other
+/// than PNG are saved as `jpg`
+fn cover_extension(data: &[u8]) -> &'static str {
+    if image::guess_format(data).is_ok_and(|x| x == image::ImageFormat::Png) {
+        "png"
+    } else {
+        "jpg"
+    }
+}
+
+/// Returns path of existing cover with `path` name in any of
+/// [`COVER_EXTENSIONS`]
+fn existing_cover(path: &Path) -> anyhow::Result<Option<PathBuf>> {
+    for extension in COVER_EXTENSIONS {
+        let path = path.with_extension(extension);
+        if path.try_exists()? {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// Downscales image until it fits into `limit` bytes. PNG images are
+/// encoded as PNG, other images as JPEG
+fn resize_image(data: &[u8], limit: u64) -> anyhow::Result<Vec<u8>> {
+    let format = image::guess_format(data).context("Unknown image format")?;
+    let mut image = image::load_from_memory_with_format(data, format)
+        .context("Failed to decode image")?;
+    loop {
+        let mut resized = Vec::new();
+        if format == image::ImageFormat::Png {
+            image.write_to(
+                &mut std::io::Cursor::new(&mut resized),
+                image::ImageFormat::Png,
+            )
+        } else {
+            image::codecs::jpeg::JpegEncoder::new_with_quality(
+                &mut resized,
+                90,
+            )
+            .encode_image(&image)
+        }
+        .context("Failed to encode image")?;
+        let size = resized.len() as u64;
+        if size <= limit {
+            return Ok(resized);
+        }
+        if image.width() <= 1 || image.height() <= 1 {
+            return Err(anyhow::anyhow!(
+                "Failed to resize cover to {limit} bytes"
+            ));
+        }
+        // image size is roughly proportional to the number of pixels
+        #[expect(clippy::cast_precision_loss)]
+        let scale = ((limit as f64 / size as f64).sqrt() * 0.95).min(0.9);
+        #[expect(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let (width, height) = (
+            (f64::from(image.width()) * scale).max(1.0) as u32,
+            (f64::from(image.height()) * scale).max(1.0) as u32,
+        );
+        image = image.resize_exact(
+            width,
+            height,
+            image::imageops::FilterType::Lanczos3,
+        );
+    }
+}
+
+/// Finds FLAC files with track id tag in folder and its subfolders.
+/// Returns paths by track id
+fn scan_track_ids(folder: &Path) -> HashMap<String, PathBuf> {
+    let mut tracks = HashMap::new();
+    let mut folders = vec![folder.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        let Ok(entries) = std::fs::read_dir(&folder) else {
+            tracing::debug!("Failed to read folder {}", folder.display());
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if entry.file_type().is_ok_and(|x| x.is_dir()) {
+                folders.push(path);
+            } else if path.extension().is_some_and(|x| x == "flac") {
+                if let Some(track_id) = file_track_id(&path) {
+                    tracks.insert(track_id, path);
+                }
+            }
+        }
+    }
+    tracks
+}
+
+/// Returns quality of downloaded track file by its extension
+fn file_quality(path: &Path) -> Quality {
+    if path.extension().is_some_and(|x| x == "mp3") {
+        Quality::MP3High
+    } else {
+        Quality::Flac
+    }
+}
+
+/// Reads track id from tags of FLAC or MP3 file
+fn file_track_id(path: &Path) -> Option<String> {
+    if path.extension().is_some_and(|x| x == "mp3") {
+        return id3::Tag::read_from_path(path)
+            .ok()?
+            .extended_texts()
+            .find(|x| x.description == "TRACK_ID")
+            .map(|x| x.value.clone());
+    }
+    metaflac::Tag::read_from_path(path)
+        .ok()?
+        .get_vorbis("TRACK_ID")?
+        .next()
+        .map(str::to_owned)
+}
+
+fn is_lyrics_placeholder(text: &str, placeholders: &[String]) -> bool {
+    let text = text.trim().trim_end_matches('.').to_lowercase();
+    placeholders
+        .iter()
+        .any(|placeholder| placeholder.trim().to_lowercase() == text)
+}
+
+/// Replaces `{name}` placeholders in template with sanitized values.
+///
+/// Unknown placeholders are left as is. Empty brackets and dangling
+/// dashes left by empty values are removed, `/` in template separates
+/// folders
+fn render_template(template: &str, values: &[(&str, &str)]) -> PathBuf {
+    let mut rendered = template.to_owned();
+    for (name, value) in values {
+        rendered =
+            rendered.replace(&format!("{{{name}}}"), &sanitize_path(value));
+    }
+    rendered
+        .split('/')
+        .map(|component| {
+            let mut component = component
+                .replace("()", "")
+                .replace("[]", "")
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            while let Some(x) = component
+                .strip_suffix(" -")
+                .or_else(|| component.strip_prefix("- "))
+            {
+                component = x.trim().to_owned();
+            }
+            truncate_filename(&component)
+        })
+        .filter(|component| !component.is_empty())
+        .collect()
+}
+
+/// Name of the file with release id in every album folder
+const RELEASE_ID_FILE: &str = ".release_id";
+
+/// Creates album folder rendered from `template` and returns its path.
+///
+/// Folders of templates with `{album}` belong to a single release. If such
+/// folder belongs to another release, release id is appended to the folder
+/// name. Folders of other templates, e.g. `{artist}`, are shared by releases
+fn create_release_folder(
+    folder: &Path,
+    release_id: &str,
+    template: &str,
+) -> anyhow::Result<PathBuf> {
+    if !template.contains("{album}") {
+        std::fs::create_dir_all(folder).with_context(|| {
+            format!("Failed to create folder {}", folder.display())
+        })?;
+        return Ok(folder.to_path_buf());
+    }
+    if let Some(path) = claim_folder(folder, release_id)? {
+        return Ok(path);
+    }
+
+    let unique = PathBuf::from(format!("{} [{release_id}]", folder.display()));
+    tracing::warn!(
+        "Folder {} belongs to another release, using {}",
+        folder.display(),
+        unique.display()
+    );
+    claim_folder(&unique, release_id)?.with_context(|| {
+        format!("Folder {} belongs to another release", unique.display())
+    })
+}
+
+/// Finds existing album folder of the release without creating or
+/// claiming it. Mirrors [`create_release_folder`]
+fn find_release_folder(
+    folder: &Path,
+    release_id: &str,
+    template: &str,
+) -> anyhow::Result<Option<PathBuf>> {
+    if !template.contains("{album}") {
+        return Ok(folder.is_dir().then(|| folder.to_path_buf()));
+    }
+    let unique = PathBuf::from(format!("{} [{release_id}]", folder.display()));
+    for candidate in [folder, unique.as_path()] {
+        if !candidate.is_dir() {
+            continue;
+        }
+        let marker = candidate.join(RELEASE_ID_FILE);
+        if !marker.try_exists()? {
+            return Ok(Some(candidate.to_path_buf()));
+        }
+        let owner = std::fs::read_to_string(&marker)
+            .with_context(|| format!("Failed to read {}", marker.display()))?;
+        if owner.trim() == release_id {
+            return Ok(Some(candidate.to_path_buf()));
+        }
+    }
+    Ok(None)
+}
+
+/// Creates folder and marks it as belonging to the release.
+///
+/// Returns `None` if folder is already marked with another release id.
+/// Existing folders without a mark are claimed by the release
+fn claim_folder(
+    folder: &Path,
+    release_id: &str,
+) -> anyhow::Result<Option<PathBuf>> {
+    std::fs::create_dir_all(folder).with_context(|| {
+        format!("Failed to create folder {}", folder.display())
+    })?;
+    let marker = folder.join(RELEASE_ID_FILE);
+    // creating the marker is atomic, so only one of concurrent downloads
+    // claims the folder
+    match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&marker)
+    {
+        Ok(mut file) => {
+            std::io::Write::write_all(&mut file, release_id.as_bytes())
+                .with_context(|| {
+                    format!("Failed to write {}", marker.display())
+                })?;
+            return Ok(Some(folder.to_path_buf()));
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {},
+        Err(e) => {
+            return Err(e).with_context(|| {
+                format!("Failed to write {}", marker.display())
+            });
+        },
+    }
+    let owner = std::fs::read_to_string(&marker)
+        .with_context(|| format!("Failed to read {}", marker.display()))?;
+    Ok((owner.trim() == release_id).then(|| folder.to_path_buf()))
+}
+
+fn cover_hash(data: &[u8]) -> u64 {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Checks that files have the same contents
+fn same_contents(a: &Path, b: &Path) -> anyhow::Result<bool> {
+    if std::fs::metadata(a)?.len() != std::fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    Ok(std::fs::read(a)? == std::fs::read(b)?)
+}
+
+/// Hardlinks file or copies it if hardlinks are not supported
+fn link_file(from: &Path, to: &Path) -> anyhow::Result<()> {
+    if let Err(e) = std::fs::hard_link(from, to) {
+        tracing::debug!("Failed to hardlink {}: {e}", from.display());
+        std::fs::copy(from, to).with_context(|| {
+            format!("Failed to copy {} to {}", from.display(), to.display())
+        })?;
+    }
+    Ok(())
+}
+
+/// Renames file or copies it when it can't be renamed, e.g. when files
+/// are on different file systems
+fn move_file(from: &Path, to: &Path) -> anyhow::Result<()> {
+    if let Err(e) = std::fs::rename(from, to) {
+        tracing::debug!(
+            "Failed to rename {} to {}, copying it: {e}",
+            from.display(),
+            to.display()
+        );
+        std::fs::copy(from, to).with_context(|| {
+            format!("Failed to copy {} to {}", from.display(), to.display())
+        })?;
+        std::fs::remove_file(from)
+            .with_context(|| format!("Failed to remove {}", from.display()))?;
+    }
+    Ok(())
+}
+
+/// Parses genre map lines like `from=to`.
+/// Empty lines and lines starting with `#` are ignored
+fn parse_genre_map(text: &str) -> anyhow::Result<HashMap<String, String>> {
+    let mut genre_map = HashMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (from, to) = line
+            .split_once('=')
+            .with_context(|| format!("line {} has no `=`", number + 1))?;
+        genre_map.insert(from.trim().to_lowercase(), to.trim().to_owned());
+    }
+    Ok(genre_map)
+}
+
+/// Checks that release date like `20240131` is within years range.
+/// Dates without year are always in range
+fn is_in_years(
+    date: &str,
+    min_year: Option<i32>,
+    max_year: Option<i32>,
+) -> bool {
+    let Ok(year) = date.chars().take(4).collect::<String>().parse::<i32>()
+    else {
+        return true;
+    };
+    min_year.is_none_or(|x| year >= x) && max_year.is_none_or(|x| year <= x)
+}
+
+/// Removes locale from URLs like `https://zvuk.com/ru/release/123`
+fn strip_locale(url: &str) -> String {
+    let stripped = url
+        .strip_prefix(ZVUK_HOST)
+        .and_then(|x| x.strip_prefix('/'))
+        .and_then(|x| x.split_once('/'))
+        .filter(|(locale, _)| {
+            (2..=5).contains(&locale.len())
+                && locale.chars().all(|c| c.is_ascii_alphabetic() || c == '-')
+        });
+    stripped
+        .map(|(_, path)| format!("{ZVUK_HOST}/{path}"))
+        .filter(|x| {
+            [
+                ZVUK_RELEASE_PREFIX,
+                ZVUK_TRACKS_PREFIX,
+                ZVUK_PLAYLIST_PREFIX,
+                ZVUK_ARTIST_PREFIX,
+            ]
+            .iter()
+            .any(|prefix| x.starts_with(prefix))
+        })
+        .unwrap_or_else(|| url.to_owned())
+}
+
+const FEAT_KEYWORDS: &[&str] = &["featuring ", "feat. ", "ft. "];
+
+/// Splits title like `Song (feat. Artist)` into title without featured
+/// artists and featured artists
+fn split_feat(title: &str) -> (String, Option<String>) {
+    // keywords are ASCII so byte offsets are the same in both strings
+    let lower = title.to_ascii_lowercase();
+
+    for keyword in FEAT_KEYWORDS {
+        let mut offset = 0;
+        while let Some(position) =
+            lower.get(offset..).and_then(|x| x.find(keyword))
+        {
+            let start = offset + position;
+            offset = start + keyword.len();
+
+            let before = lower.get(..start).unwrap_or_default();
+            let bracket = match before.chars().last() {
+                Some('(') => Some(')'),
+                Some('[') => Some(']'),
+                Some(' ') => None,
+                _ => continue,
+            };
+
+            let featured_start = start + keyword.len();
+            let (strip_start, featured_end, strip_end) = match bracket {
+                Some(close) => {
+                    let Some(end) = lower
+                        .get(featured_start..)
+                        .and_then(|x| x.find(close))
+                        .map(|x| x + featured_start)
+                    else {
+                        continue;
+                    };
+                    (start - 1, end, end + 1)
+                },
+                None => {
+                    let end = lower
+                        .get(featured_start..)
+                        .and_then(|x| x.find(['(', '[']))
+                        .map_or(lower.len(), |x| x + featured_start);
+                    (start, end, end)
+                },
+            };
+
+            let featured = title
+                .get(featured_start..featured_end)
+                .unwrap_or_default()
+                .trim();
+            let stripped = format!(
+                "{} {}",
+                title.get(..strip_start).unwrap_or_default().trim_end(),
+                title.get(strip_end..).unwrap_or_default().trim_start(),
+            );
+            if featured.is_empty() {
+                continue;
+            }
+            return (stripped.trim().to_owned(), Some(featured.to_owned()));
+        }
+    }
+    (title.to_owned(), None)
+}
+
+/// Maximum length of file name in bytes. Leaves room for quality label
+/// and `.part` suffix within 255 bytes most file systems allow
+const MAX_FILENAME_LEN: usize = 240;
+
+/// Replaces characters that can't be used in file names, control
+/// characters and trims spaces and dots around the name
+fn sanitize_path(path: &str) -> String {
+    let path = path
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect::<String>()
+        .replace(FORBIDDEN_CHARS, "_");
+    let path = path.trim_matches(|c: char| c.is_whitespace() || c == '.');
+    if is_reserved_name(path) {
+        return format!("{path}_");
+    }
+    path.to_owned()
+}
+
+#[cfg(target_os = "windows")]
+const FORBIDDEN_CHARS: [char; 9] =
+    ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+#[cfg(not(target_os = "windows"))]
+const FORBIDDEN_CHARS: [char; 1] = ['/'];
+
+/// Whether name is reserved for devices on Windows
+#[cfg(target_os = "windows")]
+fn is_reserved_name(name: &str) -> bool {
+    let name = name.split('.').next().unwrap_or_default().to_uppercase();
+    ["CON", "PRN", "AUX", "NUL"].contains(&name.as_str())
+        || ["COM", "LPT"].iter().any(|prefix| {
+            name.strip_prefix(prefix).is_some_and(|x| {
+                x.len() == 1 && x.chars().all(|c| matches!(c, '1'..='9'))
+            })
+        })
+}
+
+#[cfg(not(target_os = "windows"))]
+const fn is_reserved_name(_name: &str) -> bool {
+    false
+}
+
+/// Truncates file name to `MAX_FILENAME_LEN` bytes keeping its extension
+fn truncate_filename(name: &str) -> String {
+    if name.len() <= MAX_FILENAME_LEN {
+        return name.to_owned();
+    }
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if ext.len() <= 5 && !ext.contains(' ') => {
+            (stem, format!(".{ext}"))
+        },
+        _ => (name, String::new()),
+    };
+    let mut end = MAX_FILENAME_LEN.saturating_sub(ext.len());
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    let stem = stem.get(..end).unwrap_or_default().trim_end();
+    format!("{stem}{ext}")
+}
+
+/// Makes path absolute and adds `\\?\` prefix to it so paths longer than
+/// `MAX_PATH` (260 characters) can be used on Windows
+#[cfg(target_os = "windows")]
+fn long_path(path: &Path) -> anyhow::Result<PathBuf> {
+    const PREFIX: &str = r"\\?\";
+
+    let path = std::path::absolute(path).with_context(|| {
+        format!("Failed to make path absolute {}", path.display())
+    })?;
+    let path_str = path.to_str().context("path is not valid string")?;
+
+    if path_str.starts_with(PREFIX) {
+        Ok(path)
+    } else if let Some(unc) = path_str.strip_prefix(r"\\") {
+        Ok(PathBuf::from(format!(r"{PREFIX}UNC\{unc}")))
+    } else {
+        Ok(PathBuf::from(format!("{PREFIX}{path_str}")))
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+#[expect(clippy::unnecessary_wraps)]
+fn long_path(path: &Path) -> anyhow::Result<PathBuf> {
+    Ok(path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_api_error, claim_folder, cover_mime_type, cover_url,
+        create_release_folder, file_quality, file_track_id,
+        find_release_folder, is_in_years, is_lyrics_placeholder, is_retryable,
+        move_file, parse_artist_releases, parse_genre_map, parse_lyrics,
+        parse_retry_after, parse_stream, render_template, resize_image,
+        retry_wait, same_contents, sanitize_path, scan_track_ids, split_feat,
+        strip_locale, AudioTag, AuthFailed, Client, FlacTag, HttpStatus,
+        LyricsKind, MimeType, Picture, PlaylistInfo, Quality, ReleaseInfo,
+        ReleaseType, RequestedIds, SkipReason, StatusCode, TrackInfo,
+        MAX_FILENAME_LEN,
+    };
+    use crate::{config::Config, progress::LogProgress};
+    use clap::Parser;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn claim_release_folder() {
+        let folder = std::env::temp_dir()
+            .join(format!("zvuk-dl-claim-{}", std::process::id()));
+
+        assert_eq!(claim_folder(&folder, "1").unwrap(), Some(folder.clone()));
+        assert_eq!(claim_folder(&folder, "1").unwrap(), Some(folder.clone()));
+        assert_eq!(claim_folder(&folder, "2").unwrap(), None);
+
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+
+    #[test]
+    fn claim_release_folder_concurrently() {
+        let folder = std::env::temp_dir()
+            .join(format!("zvuk-dl-claim-race-{}", std::process::id()));
+
+        let claimed = std::thread::scope(|scope| {
+            let handles = (0..8)
+                .map(|release_id| {
+                    let folder = &folder;
+                    scope.spawn(move || {
+                        claim_folder(folder, &release_id.to_string()).unwrap()
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .filter_map(|handle| handle.join().unwrap())
+                .count()
+        });
+        std::fs::remove_dir_all(&folder).unwrap();
+        assert_eq!(claimed, 1);
+    }
+
+    #[test]
+    fn shared_release_folder() {
+        let root = std::env::temp_dir()
+            .join(format!("zvuk-dl-shared-{}", std::process::id()));
+        let folder = root.join("Artist");
+
+        for release_id in ["1", "2"] {
+            assert_eq!(
+                create_release_folder(&folder, release_id, "{artist}")
+                    .unwrap(),
+                folder
+            );
+        }
+        assert!(!folder.join(".release_id").exists());
+
+        let album = root.join("Artist - Album");
+        let template = "{artist} - {album}";
+        assert_eq!(
+            create_release_folder(&album, "1", template).unwrap(),
+            album
+        );
+        assert_eq!(
+            create_release_folder(&album, "2", template).unwrap(),
+            root.join("Artist - Album [2]")
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn find_existing_release_folder() {
+        let root = std::env::temp_dir()
+            .join(format!("zvuk-dl-find-{}", std::process::id()));
+        let album = root.join("Artist - Album");
+        let template = "{artist} - {album}";
+
+        assert_eq!(find_release_folder(&album, "1", template).unwrap(), None);
+        assert!(!root.exists());
+
+        create_release_folder(&album, "1", template).unwrap();
+        create_release_folder(&album, "2", template).unwrap();
+        assert_eq!(
+            find_release_folder(&album, "1", template).unwrap(),
+            Some(album.clone())
+        );
+        assert_eq!(
+            find_release_folder(&album, "2", template).unwrap(),
+            Some(root.join("Artist - Album [2]"))
+        );
+        assert_eq!(find_release_folder(&album, "3", template).unwrap(), None);
+        assert!(!root.join("Artist - Album [3]").exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn detect_lyrics_placeholder() {
+        let placeholders = vec![String::from("Текст песни отсутствует")];
+
+        assert!(is_lyrics_placeholder(
+            "Текст песни отсутствует",
+            &placeholders
+        ));
+        assert!(is_lyrics_placeholder(
+            " текст песни отсутствует.\n",
+            &placeholders
+        ));
+        assert!(!is_lyrics_placeholder("Some lyrics", &placeholders));
+        assert!(!is_lyrics_placeholder("", &placeholders));
+    }
+
+    #[test]
+    fn validate_cover() {
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::RgbImage::new(2, 2)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let png = png.into_inner();
+
+        assert_eq!(cover_mime_type(&png).unwrap(), MimeType::Png);
+        assert!(cover_mime_type(png.get(..png.len() / 2).unwrap()).is_err());
+        assert!(cover_mime_type(b"<html>").is_err());
+    }
+
+    #[test]
+    fn detect_api_errors() {
+        assert!(check_api_error(&serde_json::json!({"result": {}})).is_ok());
+        assert!(check_api_error(
+            &serde_json::json!({"result": {}, "error": null})
+        )
+        .is_ok());
+
+        let error = check_api_error(&serde_json::json!({
+            "error": {"code": "unauthorized", "message": "Invalid token"},
+        }))
+        .unwrap_err();
+        assert!(error.is::<AuthFailed>());
+
+        let error = check_api_error(&serde_json::json!({
+            "error": "internal",
+        }))
+        .unwrap_err();
+        assert!(!error.is::<AuthFailed>());
+
+        for error in [
+            serde_json::json!({"code": 401, "message": "Forbidden"}),
+            serde_json::json!({"status": 401}),
+            serde_json::json!("token_expired"),
+            serde_json::json!("Please login again"),
+        ] {
+            let error =
+                check_api_error(&serde_json::json!({ "error": error }))
+                    .unwrap_err();
+            assert!(error.is::<AuthFailed>(), "{error:#}");
+        }
+        for error in [
+            serde_json::json!("Unknown author"),
+            serde_json::json!({"code": 404, "message": "No such authority"}),
+            serde_json::json!("authorization server is down"),
+        ] {
+            let error =
+                check_api_error(&serde_json::json!({ "error": error }))
+                    .unwrap_err();
+            assert!(!error.is::<AuthFailed>(), "{error:#}");
+        }
+    }
+
+    #[test]
+    fn retryable_errors() {
+        let status = |status| {
+            anyhow::Error::from(HttpStatus {
+                status,
+                host: String::from("cdn.zvuk.com"),
+                retry_after: None,
+            })
+        };
+
+        assert!(is_retryable(&status(StatusCode::SERVICE_UNAVAILABLE)));
+        assert!(is_retryable(&status(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(!is_retryable(&status(StatusCode::FORBIDDEN)));
+        assert!(!is_retryable(&anyhow::Error::from(AuthFailed)));
+        assert!(is_retryable(
+            &anyhow::Error::from(std::io::Error::from(
+                std::io::ErrorKind::ConnectionReset
+            ))
+            .context("Failed to read track data")
+        ));
+        assert!(!is_retryable(&anyhow::anyhow!("tracks is not an object")));
+    }
+
+    #[test]
+    fn limit_retry_after() {
+        let delay = Duration::from_secs(1);
+        let error = |retry_after| {
+            anyhow::Error::from(HttpStatus {
+                status: StatusCode::TOO_MANY_REQUESTS,
+                host: String::from("zvuk.com"),
+                retry_after,
+            })
+            .context("Failed to download tracks metadata")
+        };
+
+        assert_eq!(retry_wait(&error(None), delay), Some(delay));
+        assert_eq!(
+            retry_wait(&error(Some(Duration::from_secs(30))), delay),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            retry_wait(&error(Some(Duration::from_secs(86_400))), delay),
+            None
+        );
+        assert_eq!(
+            retry_wait(&anyhow::anyhow!("connection reset"), delay),
+            Some(delay)
+        );
+    }
+
+    #[test]
+    fn sanitize_file_names() {
+        assert_eq!(sanitize_path("name   "), "name");
+        assert_eq!(sanitize_path(" ..name. "), "name");
+        assert_eq!(sanitize_path("new\nline"), "new line");
+        assert_eq!(sanitize_path("AC/DC"), "AC_DC");
+        #[cfg(target_os = "windows")]
+        assert_eq!(sanitize_path("AUX"), "AUX_");
+        #[cfg(not(target_os = "windows"))]
+        assert_eq!(sanitize_path("AUX"), "AUX");
+
+        let long_title = "я".repeat(300);
+        let filename = render_template(
+            "{track} - {title}.{ext}",
+            &[("track", "01"), ("title", &long_title), ("ext", "flac")],
+        );
+        let filename = filename.to_str().unwrap();
+        assert!(filename.len() <= MAX_FILENAME_LEN);
+        assert!(filename.starts_with("01 - я"));
+        assert!(filename.ends_with("я.flac"));
+    }
+
+    #[test]
+    fn render_path_templates() {
+        let values = [
+            ("artist", "AC/DC"),
+            ("album", "Back in Black"),
+            ("year", "1980"),
+            ("track", "01"),
+            ("title", "Hells Bells"),
+            ("ext", "flac"),
+        ];
+        let cases = [
+            (
+                "{artist} - {album} ({year})",
+                "AC_DC - Back in Black (1980)",
+            ),
+            ("{track} - {title}.{ext}", "01 - Hells Bells.flac"),
+            ("{artist}/{year} - {album}", "AC_DC/1980 - Back in Black"),
+            (
+                "{artist} - {album} ({label})",
+                "AC_DC - Back in Black ({label})",
+            ),
+        ];
+        for (template, expected) in cases {
+            assert_eq!(
+                render_template(template, &values),
+                std::path::PathBuf::from(expected)
+            );
+        }
+
+        let without_year =
+            [("artist", "Artist"), ("album", "Album"), ("year", "")];
+        assert_eq!(
+            render_template("{artist} - {album} ({year})", &without_year),
+            std::path::PathBuf::from("Artist - Album")
+        );
+        assert_eq!(
+            render_template("{year} - {album}", &without_year),
+            std::path::PathBuf::from("Album")
+        );
+    }
+
+    #[test]
+    fn parse_retry_after_header() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_747);
+
+        assert_eq!(
+            parse_retry_after("90", now),
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT", now),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn parse_stream_url() {
+        let single = serde_json::json!("https://cdn/flac");
+        let object = serde_json::json!({
+            "flac": "https://cdn/flac",
+            "high": "https://cdn/high",
+        });
+        let array = serde_json::json!([
+            { "quality": "high", "stream": "https://cdn/high" },
+            { "quality": "flac", "url": "https://cdn/flac" },
+        ]);
+
+        for stream in [&single, &object, &array] {
+            assert_eq!(
+                parse_stream(stream, &Quality::Flac).unwrap(),
+                "https://cdn/flac"
             );
         }
-
-        Ok(tracks)
+        for stream in [&object, &array] {
+            assert_eq!(
+                parse_stream(stream, &Quality::MP3High).unwrap(),
+                "https://cdn/high"
+            );
+            assert!(parse_stream(stream, &Quality::MP3Mid).is_err());
+        }
+        assert!(
+            parse_stream(&serde_json::Value::Null, &Quality::Flac).is_err()
+        );
     }
 
-    fn get_tracks_links(
-        &self,
-        track_ids: &[String],
-    ) -> anyhow::Result<HashMap<String, String>> {
-        tracing::info!("Getting download urls in {} quality", self.quality);
-        let mut urls = HashMap::new();
+    #[test]
+    fn parse_lyrics_response() {
+        let subtitle = parse_lyrics(&serde_json::json!({
+            "result": { "lyrics": "[00:01.00] La", "type": "subtitle" },
+        }))
+        .unwrap();
+        assert_eq!(subtitle.text, "[00:01.00] La");
+        assert!(matches!(subtitle.kind, LyricsKind::Subtitle));
 
-        for track_id in track_ids {
-            let response = self
-                .http
-                .get(ZVUK_DOWNLOAD_URL)
-                .query(&[
-                    ("quality", &self.quality.to_string()),
-                    ("id", track_id),
-                ])
-                .headers(self.default_headers.clone())
-                .send()
-                .context("Failed to download track links")?;
-
-            let body = response
-                .json::<serde_json::Value>()
-                .context("Failed to prase track links")?;
-            tracing::trace!("{ZVUK_DOWNLOAD_URL} response: {body:#?}");
-
-            urls.insert(
-                track_id.clone(),
-                body.get("result")
-                    .and_then(|x| x.get("stream"))
-                    .and_then(|x| x.as_str())
-                    .context("stream is not a string")?
-                    .to_string(),
-            );
+        for body in [
+            serde_json::json!({ "result": { "lyrics": null } }),
+            serde_json::json!({ "result": {} }),
+            serde_json::json!({ "result": null }),
+        ] {
+            let lyrics = parse_lyrics(&body).unwrap();
+            assert!(lyrics.text.is_empty());
+            assert!(matches!(lyrics.kind, LyricsKind::Lyrics));
+        }
+        assert!(parse_lyrics(&serde_json::json!({
+            "result": { "lyrics": 1 },
+        }))
+        .is_err());
+    }
 
-            std::thread::sleep(self.pause_between_getting_track_links);
+    #[test]
+    fn compare_file_contents() {
+        let dir = std::env::temp_dir()
+            .join(format!("zvuk-dl-same-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, data) in [("a", "cover"), ("b", "cover"), ("c", "cov3r")] {
+            std::fs::write(dir.join(name), data).unwrap();
         }
-        Ok(urls)
+
+        assert!(same_contents(&dir.join("a"), &dir.join("b")).unwrap());
+        assert!(!same_contents(&dir.join("a"), &dir.join("c")).unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
-    fn get_lyrics(
-        &self,
-        track_id: &str,
-        path: &Path,
-    ) -> anyhow::Result<Lyrics> {
-        tracing::info!("Getting lyrics for {}", path.display());
-        let response = self
-            .http
-            .get(ZVUK_LYRICS_URL)
-            .query(&[("track_id", track_id)])
-            .headers(self.default_headers.clone())
-            .send()
-            .context("Failed to download lyrics")?;
-        let body = response
-            .json::<serde_json::Value>()
-            .context("Failed to parse lyrics")?;
-        tracing::trace!("{ZVUK_LYRICS_URL} response: {body:#?}");
+    #[test]
+    fn move_file_to_another_folder() {
+        let dir = std::env::temp_dir()
+            .join(format!("zvuk-dl-move-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        let from = dir.join("1.flac.part");
+        let to = dir.join("target").join("01 - Title.flac");
+        std::fs::write(&from, b"data").unwrap();
 
-        let lyrics = body
-            .get("result")
-            .and_then(|x| x.get("lyrics"))
-            .and_then(|x| x.as_str())
-            .context("lyrics is not a string")?
-            .to_string();
+        move_file(&from, &to).unwrap();
 
-        let type_ = body
-            .get("result")
-            .and_then(|x| x.get("type"))
-            .and_then(|x| x.as_str())
-            .unwrap_or("lyrics")
-            .to_string();
+        assert!(!from.exists());
+        assert_eq!(std::fs::read(&to).unwrap(), b"data");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-        let lyrics_type = if type_ == "subtitle" {
-            LyricsKind::Subtitle
-        } else {
-            LyricsKind::Lyrics
+    #[test]
+    fn parse_genres() {
+        let genre_map = parse_genre_map(
+            "# comment\n\nРок = Rock\npop=Pop\n  Электроника=Electronic  \n",
+        )
+        .unwrap();
+
+        assert_eq!(genre_map.len(), 3);
+        assert_eq!(genre_map.get("рок").map(String::as_str), Some("Rock"));
+        assert_eq!(genre_map.get("pop").map(String::as_str), Some("Pop"));
+        assert_eq!(
+            genre_map.get("электроника").map(String::as_str),
+            Some("Electronic")
+        );
+        assert!(parse_genre_map("rock").is_err());
+    }
+
+    #[test]
+    fn filter_release_years() {
+        assert!(is_in_years("20240131", None, None));
+        assert!(is_in_years("20240131", Some(2024), Some(2024)));
+        assert!(is_in_years("20240131", Some(2020), None));
+        assert!(!is_in_years("20240131", Some(2025), None));
+        assert!(!is_in_years("20240131", None, Some(2023)));
+        assert!(is_in_years("null", Some(2025), Some(2025)));
+    }
+
+    #[test]
+    fn stdout_requires_single_track() {
+        let ids = |urls: &[&str]| {
+            RequestedIds::parse(
+                &urls.iter().map(|x| (*x).to_owned()).collect::<Vec<_>>(),
+            )
         };
 
-        Ok(Lyrics {
-            kind: lyrics_type,
-            text: lyrics,
-        })
+        assert_eq!(
+            ids(&["https://zvuk.com/track/1"]).single_track().unwrap(),
+            "1"
+        );
+        for urls in [
+            &["https://zvuk.com/track/1", "https://zvuk.com/playlist/2"][..],
+            &["https://zvuk.com/track/1", "https://zvuk.com/artist/2"],
+            &["https://zvuk.com/track/1", "https://zvuk.com/release/2"],
+            &["https://zvuk.com/track/1", "https://example.com/track/2"],
+            &["https://zvuk.com/track/1", "https://zvuk.com/track/2"],
+            &[],
+        ] {
+            assert!(ids(urls).single_track().is_err(), "{urls:?}");
+        }
     }
 
-    fn download_cover(&self, url: &str, path: &Path) -> anyhow::Result<()> {
-        if !path.try_exists()? {
-            tracing::info!("Downloading cover {}", path.display());
-            let response = self.http.get(url).send()?;
-            std::fs::write(path, response.bytes()?)?;
+    #[test]
+    fn strip_url_locale() {
+        let cases = [
+            (
+                "https://zvuk.com/ru/release/123",
+                "https://zvuk.com/release/123",
+            ),
+            (
+                "https://zvuk.com/en/track/456",
+                "https://zvuk.com/track/456",
+            ),
+            (
+                "https://zvuk.com/en-us/track/456",
+                "https://zvuk.com/track/456",
+            ),
+            (
+                "https://zvuk.com/release/123",
+                "https://zvuk.com/release/123",
+            ),
+            ("https://zvuk.com/track/456", "https://zvuk.com/track/456"),
+            (
+                "https://zvuk.com/ru/playlist/789",
+                "https://zvuk.com/playlist/789",
+            ),
+            (
+                "https://example.com/ru/track/456",
+                "https://example.com/ru/track/456",
+            ),
+        ];
+
+        for (url, expected) in cases {
+            assert_eq!(strip_locale(url), expected);
         }
+    }
 
-        if self.resize_cover
-            && std::fs::metadata(path)?.len() > self.resize_cover_limit
-        {
-            tracing::debug!("Resizing cover {}", path.display());
+    #[test]
+    fn parse_artist_releases_response() {
+        let releases = parse_artist_releases(&serde_json::json!({
+            "data": {"getArtists": [{"releases": [
+                {"id": "1", "type": "album"},
+                {"id": 2, "type": "single"},
+                {"id": "3", "type": "podcast"},
+            ]}]},
+        }))
+        .unwrap();
 
-            let path_str =
-                path.to_str().context("Failed to convert path to str")?;
-            let command_str = self
-                .resize_command
-                .split_whitespace()
-                .map(|x| {
-                    x.replace("{source}", path_str)
-                        .replace("{target}", path_str)
-                })
-                .collect::<Vec<String>>();
-            let (command, args) = command_str
-                .split_first()
-                .context("Failed to parse resize command")?;
+        assert_eq!(
+            releases,
+            vec![
+                ("1".to_owned(), Some(ReleaseType::Album)),
+                ("2".to_owned(), Some(ReleaseType::Single)),
+                ("3".to_owned(), None),
+            ]
+        );
+        assert!(parse_artist_releases(&serde_json::json!({
+            "errors": [{"message": "bad request"}],
+        }))
+        .is_err());
+    }
 
-            let status = std::process::Command::new(command)
-                .args(args)
-                .status()
-                .context("Failed to run resize command")?;
-            if !status.success() {
-                return Err(anyhow::anyhow!("Failed to resize cover"));
-            }
+    #[test]
+    fn resize_cover_image() {
+        let image = image::RgbImage::from_fn(512, 512, |x, y| {
+            // noise doesn't compress well
+            let [r, g, b, _] = (x.wrapping_mul(2_654_435_761)
+                ^ y.wrapping_mul(2_246_822_519))
+            .to_le_bytes();
+            image::Rgb([r, g, b])
+        });
+        let image = image::DynamicImage::ImageRgb8(image);
+
+        for (format, mime_type) in [
+            (image::ImageFormat::Png, MimeType::Png),
+            (image::ImageFormat::Jpeg, MimeType::Jpeg),
+        ] {
+            let mut data = Vec::new();
+            image
+                .write_to(&mut std::io::Cursor::new(&mut data), format)
+                .unwrap();
+
+            let resized = resize_image(&data, 20_000).unwrap();
+            assert!(resized.len() <= 20_000);
+            assert_eq!(cover_mime_type(&resized).unwrap(), mime_type);
         }
-        Ok(())
     }
 
-    fn get_and_save_track(
-        &self,
-        url: &str,
-        track_info: &TrackInfo,
-        release_info: &ReleaseInfo,
-    ) -> anyhow::Result<()> {
-        let folder = sanitize_path(&format!(
-            "{} - {} ({})",
-            release_info.author,
-            release_info.album,
-            release_info.date.chars().take(4).collect::<String>()
-        ));
-        let folder = PathBuf::from(folder);
+    #[test]
+    fn parse_playlist() {
+        let playlist = PlaylistInfo::try_from(&serde_json::json!({
+            "title": "Playlist",
+            "track_ids": [3, 1, 2],
+        }))
+        .unwrap();
 
-        std::fs::create_dir_all(&folder).with_context(|| {
-            format!("Failed to create folder {}", folder.display())
-        })?;
+        assert_eq!(playlist.title, "Playlist");
+        assert_eq!(playlist.track_ids, vec!["3", "1", "2"]);
+    }
 
-        let cover_path = folder.join("cover.jpg");
-        self.download_cover(&track_info.image, &cover_path)
-            .context("Failed to download and process album cover")?;
+    fn track_json() -> serde_json::Value {
+        serde_json::json!({
+            "id": 128_672_726,
+            "credits": "Artist, Guest",
+            "artist_names": ["Artist", "Guest"],
+            "title": "Title",
+            "release_title": "Album",
+            "release_id": 29_970_563,
+            "genres": ["rock", "pop"],
+            "position": 3,
+            "has_flac": true,
+            "duration": 215,
+            "composer": "Composer, Lyricist",
+            "bpm": 120,
+            "availability": 1,
+            "lyrics": true,
+            "isrc": "ruabc2400001",
+            "explicit": true,
+            "image": {
+                "src": "https://cdn.zvuk.com/pic?id=1&size={size}&ext=jpg",
+            },
+        })
+    }
 
-        let filename = sanitize_path(&format!(
-            "{:02} - {}.{}",
-            track_info.number,
-            track_info.name,
-            self.quality.extension()
-        ));
-        let filename = PathBuf::from(filename);
-        let filepath = folder.join(filename);
+    /// Writes FLAC file without audio and tags
+    fn write_empty_flac(path: &std::path::Path) {
+        // marker and the last metadata block with empty stream info
+        let mut flac = b"fLaC\x80\x00\x00\x22".to_vec();
+        flac.extend([0; 34]);
+        std::fs::write(path, flac).unwrap();
+    }
 
-        tracing::info!("Downloading {}", filepath.display());
+    #[test]
+    fn embed_flac_cover() {
+        let path = std::env::temp_dir()
+            .join(format!("zvuk-dl-cover-{}.flac", std::process::id()));
+        write_empty_flac(&path);
 
-        let response = self
-            .http
-            .get(url)
-            .send()
-            .context("Failed to download track")?;
-        std::fs::write(
-            &filepath,
-            response.bytes().context("Failed to read track data")?,
+        let mut tags: Box<dyn AudioTag + Send + Sync> =
+            Box::new(FlacTag::new());
+        tags.set_album_cover(Picture {
+            mime_type: MimeType::Jpeg,
+            data: b"cover",
+        });
+        let release_info = ReleaseInfo {
+            track_ids: Vec::new(),
+            track_count: 1,
+            label: String::from("Label"),
+            date: String::from("20240101"),
+            album: String::from("Album"),
+            author: String::from("Artist"),
+        };
+        Client::write_extra_tags_flac(
+            &path,
+            &TrackInfo::try_from(&track_json()).unwrap(),
+            &release_info,
+            tags,
+            None,
         )
-        .context("Failed to save track on disk")?;
-
-        self.write_tags(&filepath, &cover_path, track_info, release_info)?;
+        .unwrap();
 
-        Ok(())
+        let tag = metaflac::Tag::read_from_path(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let pictures = tag.pictures().collect::<Vec<_>>();
+        assert_eq!(pictures.len(), 1);
+        assert_eq!(pictures.first().unwrap().data, b"cover");
+        assert_eq!(
+            tag.get_vorbis("TRACK_ID").unwrap().collect::<Vec<_>>(),
+            vec!["128672726"]
+        );
     }
 
-    fn write_tags(
-        &self,
-        filepath: &Path,
-        cover_path: &PathBuf,
-        track_info: &TrackInfo,
-        release_info: &ReleaseInfo,
-    ) -> anyhow::Result<()> {
-        let mut tags: Box<dyn AudioTag + Send + Sync> = match self.quality {
-            Quality::Flac => FlacTag::read_from_path(filepath).map_or_else(
-                |_| {
-                    tracing::trace!("Failed to read FLAC tag from file");
-                    Box::new(FlacTag::new())
-                },
-                Box::new,
-            ),
-            Quality::MP3High | Quality::MP3Mid => {
-                Id3v2Tag::read_from_path(filepath).map_or_else(
-                    |_| {
-                        tracing::trace!("Failed to read ID3v2 tag from file");
-                        Box::new(Id3v2Tag::new())
-                    },
-                    Box::new,
-                )
-            },
-        };
+    #[test]
+    fn find_tracks_by_id() {
+        let dir = std::env::temp_dir()
+            .join(format!("zvuk-dl-scan-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("Album")).unwrap();
+        let path = dir.join("Album").join("01 - Renamed.flac");
+        write_empty_flac(&path);
+        let mut tag = metaflac::Tag::read_from_path(&path).unwrap();
+        tag.set_vorbis("TRACK_ID", vec!["42"]);
+        tag.save().unwrap();
+        std::fs::write(dir.join("cover.jpg"), b"cover").unwrap();
 
-        tags.set_artist(&track_info.author);
-        tags.set_title(&track_info.name);
-        tags.set_album_title(&release_info.album);
-        tags.set_track_number(track_info.number.try_into()?);
-        tags.set_total_tracks(release_info.track_count.try_into()?);
-        tags.set_genre(&track_info.genre);
+        let tracks = scan_track_ids(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks.get("42"), Some(&path));
+    }
 
-        if let Ok(date) =
-            NaiveDate::parse_from_str(&release_info.date, "%Y%m%d")
-        {
-            tags.set_date(id3::Timestamp {
-                year: date.year(),
-                month: u8::try_from(date.month()).ok(),
-                day: u8::try_from(date.day()).ok(),
-                hour: None,
-                minute: None,
-                second: None,
-            });
-            tags.set_year(date.year());
-        }
+    #[test]
+    fn read_mp3_track_id() {
+        use id3::TagLike;
 
-        if self.embed_cover {
-            let cover = Picture {
-                mime_type: MimeType::Jpeg,
-                data: &std::fs::read(cover_path)
-                    .context("Failed to read cover file for embedding")?,
-            };
-            tags.set_album_cover(cover);
-        }
+        let path = std::env::temp_dir()
+            .join(format!("zvuk-dl-track-id-{}.mp3", std::process::id()));
+        std::fs::write(&path, b"").unwrap();
+        let mut tag = id3::Tag::new();
+        tag.add_frame(id3::frame::ExtendedText {
+            description: String::from("TRACK_ID"),
+            value: String::from("42"),
+        });
+        tag.write_to_path(&path, id3::Version::Id3v24).unwrap();
 
-        let lyrics = if self.download_lyrics && track_info.lyrics {
-            let lyrics = self
-                .get_lyrics(&track_info.track_id, filepath)
-                .context("Failed to get lyrics")?;
-            if lyrics.text.is_empty() {
-                tracing::warn!("No lyrics for {}", filepath.display());
-            }
-            Some(lyrics)
-        } else {
-            None
-        };
+        let track_id = file_track_id(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(track_id.as_deref(), Some("42"));
+        assert!(matches!(file_quality(&path), Quality::MP3High));
+        assert!(matches!(
+            file_quality(std::path::Path::new("01 - Title.flac")),
+            Quality::Flac
+        ));
+    }
 
-        match self.quality {
-            Quality::Flac => {
-                Self::write_extra_tags_flac(
-                    filepath,
-                    track_info,
-                    release_info,
-                    tags,
-                    lyrics.as_ref(),
-                )?;
-            },
-            Quality::MP3High | Quality::MP3Mid => {
-                Self::write_extra_tags_mp3(
-                    filepath,
-                    track_info,
-                    release_info,
-                    tags,
-                    lyrics.as_ref(),
-                )?;
-            },
-        }
+    #[test]
+    fn skip_flac_below_min_format() {
+        let config = Config::parse_from([
+            "zvuk-dl",
+            "--token=token",
+            "--min-bit-depth=16",
+            "https://zvuk.com/track/1",
+        ]);
+        let client = Client::new(&config, Box::new(LogProgress)).unwrap();
+        let path = std::env::temp_dir()
+            .join(format!("zvuk-dl-format-{}.flac", std::process::id()));
+        // empty stream info has 1 bit per sample
+        write_empty_flac(&path);
 
-        Ok(())
+        assert!(client
+            .reject_flac_format("1", &path, &Quality::Flac)
+            .unwrap());
+        assert!(!path.exists());
+        assert!(client.failures.lock().unwrap().is_empty());
+        assert_eq!(
+            client.skipped.lock().unwrap().get("1"),
+            Some(&SkipReason::BelowMinFormat)
+        );
     }
 
-    fn write_extra_tags_flac(
-        filepath: &Path,
-        track_info: &TrackInfo,
-        release_info: &ReleaseInfo,
-        tags: Box<dyn AudioTag + Send + Sync>,
-        lyrics: Option<&Lyrics>,
-    ) -> anyhow::Result<()> {
-        let mut flactag: metaflac::Tag = tags.into();
-        let vorbis_tags = flactag.vorbis_comments_mut();
+    #[test]
+    fn replace_existing_cover_with_cover_file() {
+        let dir = std::env::temp_dir()
+            .join(format!("zvuk-dl-cover-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cover_file = dir.join("local.png");
+        std::fs::write(&cover_file, b"\x89PNG\r\n\x1a\nlocal").unwrap();
+        std::fs::write(dir.join("cover.jpg"), b"zvuk").unwrap();
+        let cover_arg = format!("--cover-file={}", cover_file.display());
+        let config = Config::parse_from([
+            "zvuk-dl",
+            "--token=token",
+            "--resize-cover=false",
+            cover_arg.as_str(),
+            "https://zvuk.com/track/1",
+        ]);
+        let client = Client::new(&config, Box::new(LogProgress)).unwrap();
 
-        vorbis_tags.set("COPYRIGHT", vec![&release_info.label]);
-        vorbis_tags.set("RELEASE_ID", vec![&track_info.release_id]);
-        vorbis_tags.set("TRACK_ID", vec![&track_info.track_id]);
+        let path = client.download_cover(None, &dir.join("cover.jpg"));
+        let jpg_exists = dir.join("cover.jpg").exists();
+        let data = std::fs::read(dir.join("cover.png"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(path.unwrap(), dir.join("cover.png"));
+        assert!(!jpg_exists);
+        assert_eq!(data.unwrap(), b"\x89PNG\r\n\x1a\nlocal");
+    }
 
-        if let Some(lyrics) = lyrics {
-            if !lyrics.text.is_empty() {
-                vorbis_tags.set_lyrics(vec![&lyrics.text]);
-            }
-        }
+    #[test]
+    fn restore_duplicates_of_failed_tracks() {
+        let config = Config::parse_from([
+            "zvuk-dl",
+            "--token=token",
+            "--dedupe-by-isrc",
+            "https://zvuk.com/track/1",
+        ]);
+        let client = Client::new(&config, Box::new(LogProgress)).unwrap();
+        client.isrcs.lock().unwrap().extend([
+            (String::from("ISRC1"), String::from("1")),
+            (String::from("ISRC3"), String::from("3")),
+        ]);
+        client.duplicates.lock().unwrap().extend([
+            (String::from("1"), vec![String::from("2")]),
+            (String::from("3"), vec![String::from("4")]),
+        ]);
 
-        let mut tags: FlacTag = flactag.into();
-        tags.write_to_path(
-            filepath.to_str().context("filepath is not valid string")?,
-        )
-        .context("Failed to write tags to file")?;
-        Ok(())
+        assert_eq!(client.take_duplicates(&[String::from("1")]), ["2"]);
+        assert!(!client.isrcs.lock().unwrap().contains_key("ISRC1"));
+        assert!(client.isrcs.lock().unwrap().contains_key("ISRC3"));
+        let skipped = client.skipped.lock().unwrap();
+        assert!(!skipped.contains_key("2"));
+        assert_eq!(skipped.get("4"), Some(&SkipReason::DuplicateIsrc));
+        drop(skipped);
+        assert!(client.take_duplicates(&[]).is_empty());
     }
 
-    fn write_extra_tags_mp3(
-        filepath: &Path,
-        _track_info: &TrackInfo,
-        release_info: &ReleaseInfo,
-        tags: Box<dyn AudioTag + Send + Sync>,
-        lyrics: Option<&Lyrics>,
-    ) -> anyhow::Result<()> {
-        let mut mp3tags: id3::Tag = tags.into();
+    #[test]
+    fn parse_track() {
+        let track_info = TrackInfo::try_from(&track_json()).unwrap();
 
-        mp3tags.set_text("TCOP", &release_info.label);
+        assert_eq!(track_info.track_id, "128672726");
+        assert_eq!(track_info.artists, vec!["Artist", "Guest"]);
+        assert_eq!(track_info.release_id, "29970563");
+        assert_eq!(track_info.genre, "rock, pop");
+        assert_eq!(track_info.number, 3);
+        assert_eq!(track_info.isrc.as_deref(), Some("RUABC2400001"));
+        assert_eq!(
+            track_info.image.as_deref(),
+            Some("https://cdn.zvuk.com/pic?id=1&size={size}&ext=jpg")
+        );
+        assert!(track_info.has_flac);
+        assert!(track_info.explicit);
+        assert_eq!(track_info.duration, Some(215));
+        assert_eq!(track_info.composers, vec!["Composer", "Lyricist"]);
+        assert_eq!(track_info.bpm, Some(120));
+        assert_eq!(track_info.availability, Some(1));
+    }
 
-        if let Some(lyrics) = lyrics {
-            if !lyrics.text.is_empty() {
-                mp3tags.add_frame(frame::Lyrics {
-                    lang: String::new(),
-                    description: String::new(),
-                    text: lyrics.text.clone(),
-                });
-            }
-        }
+    #[test]
+    fn cover_url_size() {
+        let template = "https://cdn.zvuk.com/pic?id=1&size={size}&ext=jpg";
 
-        let mut tags: Id3v2Tag = mp3tags.into();
-        tags.write_to_path(
-            filepath.to_str().context("filepath is not valid string")?,
-        )
-        .context("Failed to write tags to file")?;
-        Ok(())
+        assert_eq!(
+            cover_url(template, "1000x1000"),
+            "https://cdn.zvuk.com/pic?id=1&size=1000x1000&ext=jpg"
+        );
+        assert_eq!(
+            cover_url(template, "default"),
+            "https://cdn.zvuk.com/pic?id=1"
+        );
     }
-}
 
-pub fn download(config: &Config) -> anyhow::Result<()> {
-    let mut release_ids = Vec::new();
-    let mut track_ids = Vec::new();
-
-    for url in &config.urls {
-        if let Some(url) = url.strip_prefix(ZVUK_RELEASE_PREFIX) {
-            release_ids.push(url.to_owned());
-        } else if let Some(url) = url.strip_prefix(ZVUK_TRACKS_PREFIX) {
-            track_ids.push(url.to_owned());
-        } else {
-            tracing::warn!(
-                "This doens't look like zvuk.com URL, skipping: {}",
-                url
-            );
+    #[test]
+    fn parse_track_without_image() {
+        let mut json = track_json();
+        let object = json.as_object_mut().unwrap();
+
+        object.remove("image");
+        assert!(TrackInfo::try_from(&json).unwrap().image.is_none());
+
+        for image in
+            [serde_json::json!({ "src": null }), serde_json::Value::Null]
+        {
+            json.as_object_mut()
+                .unwrap()
+                .insert(String::from("image"), image);
+            assert!(TrackInfo::try_from(&json).unwrap().image.is_none());
         }
     }
 
-    let client = Client::new(config);
+    #[test]
+    fn split_featured_artists() {
+        let cases = [
+            ("Song (feat. Artist)", "Song", Some("Artist")),
+            ("Song [ft. A & B] (Remix)", "Song (Remix)", Some("A & B")),
+            ("Song feat. Artist", "Song", Some("Artist")),
+            (
+                "Song Featuring Artist (Live)",
+                "Song (Live)",
+                Some("Artist"),
+            ),
+            ("Песня (feat. Артист)", "Песня", Some("Артист")),
+            ("Left.", "Left.", None),
+            ("Song (Remix)", "Song (Remix)", None),
+        ];
 
-    if !release_ids.is_empty() {
-        client.download_albums(&release_ids)?;
-    }
-    if !track_ids.is_empty() {
-        client.download_tracks(&track_ids, &HashMap::new())?;
+        for (title, expected_title, expected_featured) in cases {
+            let (stripped, featured) = split_feat(title);
+            assert_eq!(stripped, expected_title);
+            assert_eq!(featured.as_deref(), expected_featured);
+        }
     }
 
-    Ok(())
-}
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn long_path_prefix() {
+        use std::path::{Path, PathBuf};
 
-#[cfg(target_os = "windows")]
-fn sanitize_path(path: &str) -> String {
-    path.replace(['<', '>', ':', '"', '/', '\\', '|', '?', '*'], "_")
-}
+        use super::long_path;
 
-#[cfg(not(target_os = "windows"))]
-fn sanitize_path(path: &str) -> String {
-    path.replace(['/'], "_")
+        assert_eq!(
+            long_path(Path::new(r"C:\Music\Album")).unwrap(),
+            PathBuf::from(r"\\?\C:\Music\Album")
+        );
+        assert_eq!(
+            long_path(Path::new(r"\\?\C:\Music\Album")).unwrap(),
+            PathBuf::from(r"\\?\C:\Music\Album")
+        );
+        assert_eq!(
+            long_path(Path::new(r"\\server\share\Album")).unwrap(),
+            PathBuf::from(r"\\?\UNC\server\share\Album")
+        );
+        assert!(long_path(Path::new("Album"))
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with(r"\\?\"));
+    }
 }